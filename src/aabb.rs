@@ -0,0 +1,90 @@
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+
+/// Axis aligned bounding box, described by its minimum and maximum corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    minimum: Point3,
+    maximum: Point3,
+}
+
+impl Aabb {
+    pub fn new(minimum: Point3, maximum: Point3) -> Self {
+        Aabb { minimum, maximum }
+    }
+
+    pub fn min(&self) -> Point3 {
+        self.minimum
+    }
+
+    pub fn max(&self) -> Point3 {
+        self.maximum
+    }
+
+    /// Returns true if the ray hits the box within the interval (t_min, t_max),
+    /// using the slab method.
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit_interval(r, t_min, t_max).is_some()
+    }
+
+    /// Returns the part of the interval (t_min, t_max) in which the ray is
+    /// inside the box, if any.
+    pub fn hit_interval(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction()[a];
+            let mut t0 = (self.minimum[a] - r.origin()[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - r.origin()[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max <= t_min {
+                return None;
+            }
+        }
+
+        Some((t_min, t_max))
+    }
+
+    /// The smallest box containing both boxes.
+    pub fn surrounding_box(box0: &Aabb, box1: &Aabb) -> Self {
+        let small = Point3::new(
+            f64::min(box0.minimum.x(), box1.minimum.x()),
+            f64::min(box0.minimum.y(), box1.minimum.y()),
+            f64::min(box0.minimum.z(), box1.minimum.z()),
+        );
+        let big = Point3::new(
+            f64::max(box0.maximum.x(), box1.maximum.x()),
+            f64::max(box0.maximum.y(), box1.maximum.y()),
+            f64::max(box0.maximum.z(), box1.maximum.z()),
+        );
+
+        Aabb::new(small, big)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+
+    /// The axis (0 = x, 1 = y, 2 = z) along which the box is longest.
+    pub fn longest_axis(&self) -> usize {
+        let extent = self.maximum - self.minimum;
+        if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let extent: Vec3 = self.maximum - self.minimum;
+        2.0 * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x())
+    }
+}
@@ -0,0 +1,327 @@
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable, HittableList};
+use crate::material::Material;
+use crate::random::Rng;
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// The rectangles have no thickness, so their bounding boxes are padded by
+/// this much along the normal.
+const AABB_PADDING: f64 = 0.0001;
+
+/// A rectangle in the plane where the coordinate along axis `N` is `k`,
+/// spanning [a0, a1] along axis `A` and [b0, b1] along axis `B`. Its outward
+/// normal points along `N` unless it is `flipped`, and its u, v coordinates
+/// run along `A` and `B`.
+pub struct AaRect<const A: usize, const B: usize, const N: usize> {
+    a0: f64,
+    a1: f64,
+    b0: f64,
+    b1: f64,
+    k: f64,
+    /// 1 if the outward normal points along `N`, or -1 if against it.
+    normal_sign: f64,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+/// A rectangle facing along z.
+pub type XYRect = AaRect<0, 1, 2>;
+/// A rectangle facing along y.
+pub type XZRect = AaRect<0, 2, 1>;
+/// A rectangle facing along x.
+pub type YZRect = AaRect<1, 2, 0>;
+
+impl XYRect {
+    pub fn new(
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        AaRect::with_bounds(x0, x1, y0, y1, k, material)
+    }
+}
+
+impl XZRect {
+    pub fn new(
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        AaRect::with_bounds(x0, x1, z0, z1, k, material)
+    }
+}
+
+impl YZRect {
+    pub fn new(
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        AaRect::with_bounds(y0, y1, z0, z1, k, material)
+    }
+}
+
+impl<const A: usize, const B: usize, const N: usize> AaRect<A, B, N> {
+    fn with_bounds(
+        a0: f64,
+        a1: f64,
+        b0: f64,
+        b1: f64,
+        k: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        AaRect {
+            a0: f64::min(a0, a1),
+            a1: f64::max(a0, a1),
+            b0: f64::min(b0, b1),
+            b1: f64::max(b0, b1),
+            k,
+            normal_sign: 1.0,
+            material,
+        }
+    }
+
+    /// The same rectangle with its outward normal pointing the other way.
+    pub fn flipped(self) -> Self {
+        AaRect {
+            normal_sign: -self.normal_sign,
+            ..self
+        }
+    }
+
+    fn point(a: f64, b: f64, n: f64) -> Point3 {
+        let mut p = Point3::default();
+        p[A] = a;
+        p[B] = b;
+        p[N] = n;
+        p
+    }
+
+    fn area(&self) -> f64 {
+        (self.a1 - self.a0) * (self.b1 - self.b0)
+    }
+}
+
+impl<const A: usize, const B: usize, const N: usize> Hittable for AaRect<A, B, N> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut Rng) -> Option<HitRecord> {
+        let t = (self.k - r.origin()[N]) / r.direction()[N];
+        // Rays parallel to the plane give an infinite or NaN t.
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+
+        let p = r.at(t);
+        let (a, b) = (p[A], p[B]);
+        if a < self.a0 || a > self.a1 || b < self.b0 || b > self.b1 {
+            return None;
+        }
+
+        let u = (a - self.a0) / (self.a1 - self.a0);
+        let v = (b - self.b0) / (self.b1 - self.b0);
+        let outward_normal = Self::point(0.0, 0.0, self.normal_sign);
+        Some(HitRecord::new(
+            p,
+            r,
+            outward_normal,
+            t,
+            u,
+            v,
+            self.material.clone(),
+        ))
+    }
+
+    fn aabb(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            Self::point(self.a0, self.b0, self.k - AABB_PADDING),
+            Self::point(self.a1, self.b1, self.k + AABB_PADDING),
+        ))
+    }
+
+    /// Points are chosen uniformly over the area of the rectangle.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let r = Ray::new(*origin, *direction, 0.0);
+        let t = (self.k - origin[N]) / direction[N];
+        if !(t > 0.001 && t.is_finite()) {
+            return 0.0;
+        }
+        let p = r.at(t);
+        if p[A] < self.a0 || p[A] > self.a1 || p[B] < self.b0 || p[B] > self.b1 {
+            return 0.0;
+        }
+
+        let distance_squared = t * t * direction.length_squared();
+        let cosine = direction[N].abs() / direction.length();
+        distance_squared / (cosine * self.area())
+    }
+
+    fn random_point(&self, _origin: &Point3, rng: &mut Rng) -> Option<Point3> {
+        Some(Self::point(
+            rng.random_f64_range(self.a0, self.a1),
+            rng.random_f64_range(self.b0, self.b1),
+            self.k,
+        ))
+    }
+}
+
+/// An axis aligned box made of six rectangles.
+pub struct BoxShape {
+    min: Point3,
+    max: Point3,
+    sides: HittableList,
+}
+
+impl BoxShape {
+    /// Create a box between two opposite corners.
+    pub fn new(p0: Point3, p1: Point3, material: Arc<dyn Material + Send + Sync>) -> Self {
+        let mut min = p0;
+        let mut max = p1;
+        for axis in 0..3 {
+            min[axis] = f64::min(p0[axis], p1[axis]);
+            max[axis] = f64::max(p0[axis], p1[axis]);
+        }
+
+        let mut sides = HittableList::default();
+        let (x0, y0, z0) = (min.x(), min.y(), min.z());
+        let (x1, y1, z1) = (max.x(), max.y(), max.z());
+        // The sides at the minimum of each axis face the other way, so that
+        // every outward normal points out of the box.
+        sides.add(Arc::new(XYRect::new(x0, x1, y0, y1, z1, material.clone())));
+        sides.add(Arc::new(
+            XYRect::new(x0, x1, y0, y1, z0, material.clone()).flipped(),
+        ));
+        sides.add(Arc::new(XZRect::new(x0, x1, z0, z1, y1, material.clone())));
+        sides.add(Arc::new(
+            XZRect::new(x0, x1, z0, z1, y0, material.clone()).flipped(),
+        ));
+        sides.add(Arc::new(YZRect::new(y0, y1, z0, z1, x1, material.clone())));
+        sides.add(Arc::new(
+            YZRect::new(y0, y1, z0, z1, x0, material).flipped(),
+        ));
+
+        BoxShape { min, max, sides }
+    }
+
+    pub fn new_arc(p0: Point3, p1: Point3, material: Arc<dyn Material + Send + Sync>) -> Arc<Self> {
+        Arc::new(BoxShape::new(p0, p1, material))
+    }
+}
+
+impl Hittable for BoxShape {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord> {
+        self.sides.hit(r, t_min, t_max, rng)
+    }
+
+    fn aabb(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.sides.pdf_value(origin, direction)
+    }
+
+    fn random_point(&self, origin: &Point3, rng: &mut Rng) -> Option<Point3> {
+        self.sides.random_point(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aarect::{BoxShape, XZRect};
+    use crate::hit::Hittable;
+    use crate::material::Lambertian;
+    use crate::random::Rng;
+    use crate::ray::Ray;
+    use crate::utility;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    #[test]
+    fn rect_hits_and_faces() {
+        let mut rng = Rng::new(0);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let rect = XZRect::new(-1.0, 1.0, -2.0, 2.0, 3.0, material);
+
+        let up = Ray::new(Point3::new(0.5, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let rec = rect.hit(&up, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert_eq!(rec.t, 3.0);
+        assert_eq!((rec.u, rec.v), (0.75, 0.75));
+        assert_eq!(rec.normal, Vec3::new(0.0, -1.0, 0.0));
+        assert!(!rec.front_face);
+
+        let down = Ray::new(Point3::new(0.5, 5.0, 1.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let rec = rect.hit(&down, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert_eq!(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!(rec.front_face);
+
+        let outside = Ray::new(Point3::new(1.5, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert!(rect.hit(&outside, 0.001, f64::INFINITY, &mut rng).is_none());
+        let parallel = Ray::new(Point3::new(0.0, 3.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert!(rect
+            .hit(&parallel, 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn box_faces_point_out() {
+        let mut rng = Rng::new(0);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let shape = BoxShape::new(
+            Point3::new(-1.0, -1.0, -1.0),
+            Point3::new(1.0, 1.0, 1.0),
+            material,
+        );
+
+        // From outside through the middle of each face, and back out of the
+        // opposite face from inside.
+        for axis in 0..3 {
+            for &side in [-1.0, 1.0].iter() {
+                let mut origin = Point3::new(0.1, 0.2, 0.3);
+                origin[axis] = 3.0 * side;
+                let mut direction = Vec3::default();
+                direction[axis] = -side;
+                let r = Ray::new(origin, direction, 0.0);
+
+                let rec = shape.hit(&r, 0.001, f64::INFINITY, &mut rng).unwrap();
+                assert!(rec.front_face, "axis {} side {}", axis, side);
+                assert_eq!(rec.normal[axis], side);
+                assert_eq!(rec.t, 2.0);
+
+                let inside = Ray::new(rec.p, direction, 0.0);
+                let rec = shape.hit(&inside, 0.001, f64::INFINITY, &mut rng).unwrap();
+                assert!(!rec.front_face, "axis {} side {}", axis, side);
+                assert_eq!(rec.normal[axis], side);
+            }
+        }
+    }
+
+    #[test]
+    fn box_pdf_integrates_to_one() {
+        let mut rng = Rng::new(1);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let shape = BoxShape::new(
+            Point3::new(1.0, 2.0, 3.0),
+            Point3::new(-1.0, 1.0, 1.0),
+            material,
+        );
+        let bbox = shape.aabb().unwrap();
+        assert_eq!(bbox.min(), Point3::new(-1.0, 1.0, 1.0));
+
+        // Integrate over all directions by sampling them uniformly.
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let count = 200_000;
+        let sum: f64 = (0..count)
+            .map(|_| shape.pdf_value(&origin, &Vec3::random_unit_vector(&mut rng)))
+            .sum();
+        let integral = 4.0 * utility::PI * sum / count as f64;
+        assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::output::Image;
+use crate::vec3::Color;
+
+/// The mean and variance of a pixel's samples, updated one sample at a time
+/// by Welford's method so they stay accurate over many samples.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunningVariance {
+    pub count: u32,
+    pub mean: f64,
+    /// The sum of the squared differences from the mean.
+    pub m2: f64,
+}
+
+impl RunningVariance {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Combine with the statistics of more samples, as if they had been
+    /// added one at a time.
+    pub fn merge(&mut self, other: &RunningVariance) {
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        let (n, m) = (self.count as f64, other.count as f64);
+        self.mean += delta * m / count as f64;
+        self.m2 += other.m2 + delta * delta * n * m / count as f64;
+        self.count = count;
+    }
+
+    /// The sample variance, or None with fewer than two samples.
+    pub fn variance(&self) -> Option<f64> {
+        if self.count < 2 {
+            None
+        } else {
+            Some(self.m2 / (self.count - 1) as f64)
+        }
+    }
+
+    /// The standard error of the mean relative to the mean. Dark pixels are
+    /// compared to a small floor instead, so that black pixels can converge.
+    pub fn relative_error(&self) -> f64 {
+        match self.variance() {
+            Some(variance) => {
+                (variance / self.count as f64).sqrt() / f64::max(self.mean.abs(), 0.01)
+            }
+            None => f64::INFINITY,
+        }
+    }
+}
+
+/// An image showing how many samples each pixel took, from dark blue for
+/// none through red to yellow for `max_samples`.
+pub fn heatmap(width: usize, height: usize, sample_counts: &[u32], max_samples: u32) -> Image {
+    let stops = [
+        Color::new(0.0, 0.0, 0.1),
+        Color::new(0.8, 0.0, 0.1),
+        Color::new(1.0, 1.0, 0.0),
+    ];
+    let pixels = sample_counts
+        .iter()
+        .map(|&count| {
+            let t = f64::min(count as f64 / max_samples as f64, 1.0) * 2.0;
+            let segment = usize::min(t as usize, 1);
+            let f = t - segment as f64;
+            (1.0 - f) * stops[segment] + f * stops[segment + 1]
+        })
+        .collect();
+    Image::new(width, height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adaptive::RunningVariance;
+
+    #[test]
+    fn welford_matches_direct_variance() {
+        let values = [0.5, 2.0, 1.25, 0.0, 3.5, 1.0, 0.75];
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 6.0;
+
+        let mut all = RunningVariance::default();
+        values.iter().for_each(|&v| all.add(v));
+        assert!((all.mean - mean).abs() < 1e-12);
+        assert!((all.variance().unwrap() - variance).abs() < 1e-12);
+
+        let (mut first, mut second) = (RunningVariance::default(), RunningVariance::default());
+        values[..3].iter().for_each(|&v| first.add(v));
+        values[3..].iter().for_each(|&v| second.add(v));
+        first.merge(&second);
+        assert_eq!(first.count, 7);
+        assert!((first.mean - mean).abs() < 1e-12);
+        assert!((first.variance().unwrap() - variance).abs() < 1e-12);
+
+        let mut flat = RunningVariance::default();
+        (0..4).for_each(|_| flat.add(0.3));
+        assert_eq!(flat.relative_error(), 0.0);
+        assert_eq!(RunningVariance::default().relative_error(), f64::INFINITY);
+    }
+}
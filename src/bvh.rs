@@ -0,0 +1,245 @@
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable, HittableList};
+use crate::random::Rng;
+use crate::ray::Ray;
+use crate::vec3::Point3;
+use std::sync::Arc;
+
+/// Number of buckets used when evaluating the surface area heuristic.
+const SAH_BUCKETS: usize = 12;
+
+/// An object to be placed in the hierarchy, along with its cached bounds.
+struct Primitive {
+    object: Arc<dyn Hittable + Send + Sync>,
+    bbox: Aabb,
+    centroid: Point3,
+}
+
+/// Bounding volume hierarchy node. Each node holds two children, which are
+/// either other nodes or the leaf objects themselves.
+#[derive(Debug)]
+pub struct BvhNode {
+    left: Arc<dyn Hittable + Send + Sync>,
+    right: Arc<dyn Hittable + Send + Sync>,
+    bbox: Aabb,
+}
+
+impl BvhNode {
+    /// Build a hierarchy over all objects in the list, splitting each node
+    /// using the surface area heuristic.
+    ///
+    /// Panics if any object in the list has no bounding box.
+    pub fn new(list: HittableList) -> Self {
+        let primitives: Vec<_> = list
+            .objects()
+            .iter()
+            .map(|object| {
+                let bbox = object
+                    .aabb()
+                    .expect("No bounding box in BvhNode constructor.");
+                Primitive {
+                    object: object.clone(),
+                    bbox,
+                    centroid: bbox.centroid(),
+                }
+            })
+            .collect();
+
+        match primitives.len() {
+            0 => BvhNode {
+                left: Arc::new(HittableList::default()),
+                right: Arc::new(HittableList::default()),
+                bbox: Aabb::new(Point3::default(), Point3::default()),
+            },
+            1 => BvhNode {
+                left: primitives[0].object.clone(),
+                right: Arc::new(HittableList::default()),
+                bbox: primitives[0].bbox,
+            },
+            _ => BvhNode::build(primitives),
+        }
+    }
+
+    /// Recursively build a node from at least two primitives.
+    fn build(mut primitives: Vec<Primitive>) -> Self {
+        let bbox = primitives
+            .iter()
+            .skip(1)
+            .fold(primitives[0].bbox, |acc, p| {
+                Aabb::surrounding_box(&acc, &p.bbox)
+            });
+
+        let right_primitives = BvhNode::split(&mut primitives);
+        let left = BvhNode::build_child(primitives);
+        let right = BvhNode::build_child(right_primitives);
+
+        BvhNode { left, right, bbox }
+    }
+
+    fn build_child(mut primitives: Vec<Primitive>) -> Arc<dyn Hittable + Send + Sync> {
+        if primitives.len() == 1 {
+            primitives.pop().unwrap().object
+        } else {
+            Arc::new(BvhNode::build(primitives))
+        }
+    }
+
+    /// Partition the primitives into two non-empty sets, leaving the left set in
+    /// `primitives` and returning the right set.
+    fn split(primitives: &mut Vec<Primitive>) -> Vec<Primitive> {
+        let centroid_bounds = primitives.iter().skip(1).fold(
+            Aabb::new(primitives[0].centroid, primitives[0].centroid),
+            |acc, p| Aabb::surrounding_box(&acc, &Aabb::new(p.centroid, p.centroid)),
+        );
+        let axis = centroid_bounds.longest_axis();
+        let axis_min = centroid_bounds.min()[axis];
+        let extent = centroid_bounds.max()[axis] - axis_min;
+
+        // A total order, so primitives with NaN bounds can't stop the build.
+        primitives.sort_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+
+        // All centroids are in the same spot, so there's nothing the heuristic
+        // can do. Split evenly instead.
+        if primitives.len() <= 2 || extent <= 0.0 {
+            let mid = primitives.len() / 2;
+            return primitives.split_off(mid);
+        }
+
+        let bucket_index = |p: &Primitive| {
+            let b = (SAH_BUCKETS as f64 * (p.centroid[axis] - axis_min) / extent) as usize;
+            b.min(SAH_BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; SAH_BUCKETS];
+        let mut bounds: [Option<Aabb>; SAH_BUCKETS] = [None; SAH_BUCKETS];
+        for p in primitives.iter() {
+            let b = bucket_index(p);
+            counts[b] += 1;
+            bounds[b] = Some(match bounds[b] {
+                Some(existing) => Aabb::surrounding_box(&existing, &p.bbox),
+                None => p.bbox,
+            });
+        }
+
+        // Cost of splitting after bucket i is proportional to the number of
+        // primitives on each side weighted by the surface area of each side.
+        // Sweep from both ends to accumulate the cost of each side.
+        let mut left_costs = [0.0; SAH_BUCKETS];
+        let mut right_costs = [0.0; SAH_BUCKETS];
+        let sweep = |order: &mut dyn Iterator<Item = usize>, costs: &mut [f64]| {
+            let mut count = 0;
+            let mut side_box: Option<Aabb> = None;
+            for b in order {
+                count += counts[b];
+                side_box = match (side_box, bounds[b]) {
+                    (Some(a), Some(b)) => Some(Aabb::surrounding_box(&a, &b)),
+                    (a, b) => a.or(b),
+                };
+                costs[b] = side_box.map_or(0.0, |bbox| count as f64 * bbox.surface_area());
+            }
+        };
+        sweep(&mut (0..SAH_BUCKETS), &mut left_costs);
+        sweep(&mut (0..SAH_BUCKETS).rev(), &mut right_costs);
+
+        let best_split = (0..SAH_BUCKETS - 1)
+            .min_by(|&a, &b| {
+                let cost_a = left_costs[a] + right_costs[a + 1];
+                let cost_b = left_costs[b] + right_costs[b + 1];
+                cost_a.total_cmp(&cost_b)
+            })
+            .unwrap();
+
+        // Primitives are sorted along the axis, so the bucket boundary is also a
+        // boundary in the vector.
+        let mid = primitives
+            .iter()
+            .position(|p| bucket_index(p) > best_split)
+            .unwrap_or(primitives.len());
+
+        if mid == 0 || mid == primitives.len() {
+            let mid = primitives.len() / 2;
+            return primitives.split_off(mid);
+        }
+
+        primitives.split_off(mid)
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord> {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return None;
+        }
+
+        let hit_left = self.left.hit(r, t_min, t_max, rng);
+        let closest_so_far = hit_left.as_ref().map_or(t_max, |rec| rec.t);
+        let hit_right = self.right.hit(r, t_min, closest_so_far, rng);
+
+        hit_right.or(hit_left)
+    }
+
+    fn aabb(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bvh::BvhNode;
+    use crate::hit::{Hittable, HittableList};
+    use crate::material::Lambertian;
+    use crate::random::Rng;
+    use crate::ray::Ray;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    #[test]
+    fn bvh_matches_list() {
+        let mut rng = Rng::new(0);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mut list = HittableList::default();
+        for i in 0..10 {
+            for j in 0..10 {
+                let center = Point3::new(i as f64, (i * j % 3) as f64, j as f64);
+                list.add(Sphere::new_arc(center, 0.3, material.clone()));
+            }
+        }
+
+        let bvh = BvhNode::new(list.clone());
+        for i in 0..20 {
+            for j in 0..20 {
+                let origin = Point3::new(-5.0, 1.0, -5.0);
+                let target = Point3::new(i as f64 * 0.5, 0.5, j as f64 * 0.5);
+                let r = Ray::new(origin, target - origin, 0.0);
+
+                let expected = list
+                    .hit(&r, 0.001, f64::INFINITY, &mut rng)
+                    .map(|rec| rec.t);
+                let actual = bvh.hit(&r, 0.001, f64::INFINITY, &mut rng).map(|rec| rec.t);
+                assert_eq!(expected, actual);
+            }
+        }
+
+        let miss = Ray::new(Point3::new(0.0, 10.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert!(bvh.hit(&miss, 0.001, f64::INFINITY, &mut rng).is_none());
+    }
+
+    #[test]
+    fn nan_bounds_dont_stop_the_build() {
+        let mut rng = Rng::new(0);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mut list = HittableList::default();
+        for x in [f64::NAN, 0.0, 2.0, f64::NAN, 4.0].iter() {
+            list.add(Sphere::new_arc(
+                Point3::new(*x, 0.0, 0.0),
+                0.5,
+                material.clone(),
+            ));
+        }
+
+        let bvh = BvhNode::new(list);
+        let r = Ray::new(Point3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = bvh.hit(&r, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert_eq!(rec.t, 4.5);
+    }
+}
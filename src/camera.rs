@@ -1,3 +1,4 @@
+use crate::random::Rng;
 use crate::ray::Ray;
 use crate::utility::degrees_to_radians;
 use crate::vec3::{Point3, Vec3};
@@ -9,12 +10,16 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_raidus: f64,
+    /// Shutter open and close times.
+    time0: f64,
+    time1: f64,
 }
 
 impl Camera {
-    /// vfov in degrees
+    /// vfov in degrees. Rays are sent at random times between the shutter
+    /// open and close times, time0 and time1.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
@@ -23,6 +28,8 @@ impl Camera {
         aspect_ratio: f64,
         aperture: f64,
         focus_dist: f64,
+        time0: f64,
+        time1: f64,
     ) -> Self {
         let theta = degrees_to_radians(vfov);
         let h = f64::tan(theta / 2.0);
@@ -47,18 +54,28 @@ impl Camera {
             lower_left_corner,
             u,
             v,
-            w,
             lens_raidus,
+            time0,
+            time1,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_raidus * Vec3::random_in_unit_disk();
+    pub fn get_ray(&self, s: f64, t: f64, rng: &mut Rng) -> Ray {
+        let rd = self.lens_raidus * Vec3::random_in_unit_disk(rng);
         let offset = self.u * rd.x() + self.v * rd.y();
 
+        // Only spend a random number on the time if the shutter is open for
+        // a while.
+        let time = if self.time1 > self.time0 {
+            rng.random_f64_range(self.time0, self.time1)
+        } else {
+            self.time0
+        };
+
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            time,
         )
     }
 }
@@ -0,0 +1,186 @@
+use crate::adaptive::RunningVariance;
+use crate::vec3::Color;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8] = b"raytracing checkpoint\n";
+const VERSION: u32 = 2;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The state of a render in progress, enough to carry on with it later.
+///
+/// The random numbers for each sample are chosen from the seed and the
+/// sample's index, so with the sums, sample counts and luminance of every
+/// pixel the render continues exactly as if it had never stopped.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// Identifies the scene and settings the render was started with, see
+    /// `settings_hash`.
+    pub settings_hash: u64,
+    pub width: usize,
+    pub height: usize,
+    /// The sum of the samples of each pixel, row by row from the top left.
+    pub sums: Vec<Color>,
+    /// The number of samples taken of each pixel.
+    pub sample_counts: Vec<u32>,
+    /// The mean and variance of the luminance of each pixel's samples.
+    pub luminance: Vec<RunningVariance>,
+}
+
+impl Checkpoint {
+    /// Write the checkpoint. The sums are written in full precision, so a
+    /// resumed render gives the same image as one that was never stopped.
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&self.settings_hash.to_le_bytes())?;
+        writer.write_all(&(self.width as u64).to_le_bytes())?;
+        writer.write_all(&(self.height as u64).to_le_bytes())?;
+
+        let pixels = self
+            .sums
+            .iter()
+            .zip(self.sample_counts.iter())
+            .zip(self.luminance.iter());
+        for ((sum, count), luminance) in pixels {
+            writer.write_all(&count.to_le_bytes())?;
+            for channel in 0..3 {
+                writer.write_all(&sum[channel].to_le_bytes())?;
+            }
+            writer.write_all(&luminance.mean.to_le_bytes())?;
+            writer.write_all(&luminance.m2.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: &mut dyn Read) -> io::Result<Checkpoint> {
+        let mut magic = vec![0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("not a checkpoint file".to_string()));
+        }
+
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported checkpoint version {}",
+                version
+            )));
+        }
+
+        let settings_hash = read_u64(reader)?;
+        let width = read_u64(reader)? as usize;
+        let height = read_u64(reader)? as usize;
+        let pixel_count = width
+            .checked_mul(height)
+            .filter(|&count| count <= 1 << 32)
+            .ok_or_else(|| invalid_data(format!("invalid image size {}x{}", width, height)))?;
+
+        let mut sums = Vec::new();
+        let mut sample_counts = Vec::new();
+        let mut luminance = Vec::new();
+        for _ in 0..pixel_count {
+            let count = read_u32(reader)?;
+            sample_counts.push(count);
+            let mut channels = [0.0; 3];
+            for channel in channels.iter_mut() {
+                *channel = f64::from_le_bytes(read_bytes(reader)?);
+            }
+            sums.push(Color::new(channels[0], channels[1], channels[2]));
+            luminance.push(RunningVariance {
+                count,
+                mean: f64::from_le_bytes(read_bytes(reader)?),
+                m2: f64::from_le_bytes(read_bytes(reader)?),
+            });
+        }
+
+        Ok(Checkpoint {
+            settings_hash,
+            width,
+            height,
+            sums,
+            sample_counts,
+            luminance,
+        })
+    }
+}
+
+fn read_bytes<const N: usize>(reader: &mut dyn Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut dyn Read) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_u64(reader: &mut dyn Read) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+/// A hash of a description of the scene and the render settings, to check
+/// that a render is resumed with the same ones. It is the 64-bit FNV-1a hash,
+/// which unlike the standard library's hasher is the same on every build.
+pub fn settings_hash(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        // The length keeps the boundaries between parts from moving.
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adaptive::RunningVariance;
+    use crate::checkpoint::{settings_hash, Checkpoint};
+    use crate::vec3::Color;
+
+    #[test]
+    fn write_and_read_back() {
+        let checkpoint = Checkpoint {
+            settings_hash: settings_hash(&[b"scene", b"settings"]),
+            width: 2,
+            height: 1,
+            sums: vec![Color::new(0.1, 2.0, 1e-300), Color::new(3.5, 0.0, 7.25)],
+            sample_counts: vec![16, 32],
+            luminance: vec![
+                RunningVariance {
+                    count: 16,
+                    mean: 0.5,
+                    m2: 0.25,
+                },
+                RunningVariance {
+                    count: 32,
+                    mean: 2.0,
+                    m2: 0.0,
+                },
+            ],
+        };
+
+        let mut data = Vec::new();
+        checkpoint.write(&mut data).unwrap();
+        let read = Checkpoint::read(&mut &data[..]).unwrap();
+        assert_eq!(read.settings_hash, checkpoint.settings_hash);
+        assert_eq!((read.width, read.height), (2, 1));
+        assert_eq!(read.sample_counts, checkpoint.sample_counts);
+        for (a, b) in read.sums.iter().zip(checkpoint.sums.iter()) {
+            assert_eq!(**a, **b);
+        }
+        assert_eq!(read.luminance[0].count, 16);
+        assert_eq!((read.luminance[0].mean, read.luminance[0].m2), (0.5, 0.25));
+
+        assert!(Checkpoint::read(&mut &data[..data.len() - 1]).is_err());
+        assert!(Checkpoint::read(&mut &b"P6\n2 1\n255\n"[..]).is_err());
+        assert_ne!(
+            settings_hash(&[b"scene", b"settings"]),
+            settings_hash(&[b"scenes", b"ettings"])
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::random::Rng;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+/// A volume of constant density, like fog or smoke, filling a boundary
+/// object. Rays passing through it scatter at random points with a
+/// probability that grows with the density and the distance travelled.
+///
+/// The boundary must be closed and convex, rays are assumed to enter and
+/// leave it only once.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable + Send + Sync>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material + Send + Sync>,
+}
+
+impl ConstantMedium {
+    /// Create a medium with the given density filling the boundary. Rays
+    /// scatter off it as the phase function material does, usually
+    /// `Isotropic`.
+    pub fn new(
+        boundary: Arc<dyn Hittable + Send + Sync>,
+        density: f64,
+        phase_function: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        assert!(density.is_finite() && density > 0.0);
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function,
+        }
+    }
+
+    pub fn new_arc(
+        boundary: Arc<dyn Hittable + Send + Sync>,
+        density: f64,
+        phase_function: Arc<dyn Material + Send + Sync>,
+    ) -> Arc<Self> {
+        Arc::new(ConstantMedium::new(boundary, density, phase_function))
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord> {
+        // Find where the ray enters and leaves the boundary, even if it starts
+        // inside it.
+        let enter = self
+            .boundary
+            .hit(r, f64::NEG_INFINITY, f64::INFINITY, rng)?;
+        let leave = self.boundary.hit(r, enter.t + 0.0001, f64::INFINITY, rng)?;
+
+        let t_enter = f64::max(enter.t, f64::max(t_min, 0.0));
+        let t_leave = f64::min(leave.t, t_max);
+        if t_enter >= t_leave {
+            return None;
+        }
+
+        // Sample the distance to the next scattering, which is exponentially
+        // distributed.
+        let ray_length = r.direction().length();
+        let distance_inside = (t_leave - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * (1.0 - rng.random_f64()).ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = t_enter + hit_distance / ray_length;
+        // The normal and face are arbitrary, scattering in a volume doesn't
+        // depend on them.
+        Some(HitRecord::new(
+            r.at(t),
+            r,
+            Vec3::new(1.0, 0.0, 0.0),
+            t,
+            0.0,
+            0.0,
+            self.phase_function.clone(),
+        ))
+    }
+
+    fn aabb(&self) -> Option<Aabb> {
+        self.boundary.aabb()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constant_medium::ConstantMedium;
+    use crate::hit::Hittable;
+    use crate::material::{Isotropic, Lambertian};
+    use crate::random::Rng;
+    use crate::ray::Ray;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    #[test]
+    fn transmittance_follows_density() {
+        let mut rng = Rng::new(0);
+        let boundary = Sphere::new_arc(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        );
+        let medium = ConstantMedium::new(boundary, 0.5, Isotropic::new(Color::new(1.0, 1.0, 1.0)));
+
+        // The ray travels a distance of 2 through the medium, so it passes
+        // through with probability exp(-0.5 * 2).
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -2.0), 0.0);
+        let count = 100_000;
+        let mut passed = 0;
+        for _ in 0..count {
+            match medium.hit(&r, 0.001, f64::INFINITY, &mut rng) {
+                Some(rec) => assert!(rec.p.z() <= 1.0 && rec.p.z() >= -1.0),
+                None => passed += 1,
+            }
+        }
+
+        let transmittance = passed as f64 / count as f64;
+        assert!(
+            (transmittance - f64::exp(-1.0)).abs() < 0.01,
+            "{}",
+            transmittance
+        );
+
+        // Rays starting inside the medium scatter before leaving it too.
+        let inside = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let scattered = (0..count)
+            .filter(|_| {
+                medium
+                    .hit(&inside, 0.001, f64::INFINITY, &mut rng)
+                    .is_some()
+            })
+            .count();
+        let expected = 1.0 - f64::exp(-0.5);
+        assert!((scattered as f64 / count as f64 - expected).abs() < 0.01);
+    }
+}
@@ -0,0 +1,537 @@
+//! The light arriving from outside the scene, seen by rays that don't hit
+//! any object.
+
+use crate::hdr::load_hdr_image;
+use crate::output::Image;
+use crate::pdf::{ConePdf, MixturePdf, Pdf, SpherePdf};
+use crate::random::Rng;
+use crate::utility;
+use crate::vec3::{Color, Vec3};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+pub trait Environment {
+    /// Returns the radiance arriving from the given direction, which does not
+    /// need to be a unit vector.
+    fn value(&self, direction: &Vec3) -> Color;
+
+    /// Choose a unit direction towards the environment, in proportion to how
+    /// much light arrives from it. Returns the direction and its probability
+    /// density over solid angle, or None if the environment can't be sampled.
+    fn sample(&self, _rng: &mut Rng) -> Option<(Vec3, f64)> {
+        None
+    }
+
+    /// Returns the probability density of `sample` choosing a direction, 0 if
+    /// the environment can't be sampled.
+    fn pdf(&self, _direction: &Vec3) -> f64 {
+        0.0
+    }
+}
+
+impl std::fmt::Debug for dyn Environment + Send + Sync {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "dyn Environment")
+    }
+}
+
+/// The same color in every direction.
+#[derive(Debug)]
+pub struct SolidBackground {
+    color: Color,
+}
+
+impl SolidBackground {
+    pub fn new(color: Color) -> Arc<Self> {
+        Arc::new(SolidBackground { color })
+    }
+}
+
+impl Environment for SolidBackground {
+    fn value(&self, _direction: &Vec3) -> Color {
+        self.color
+    }
+}
+
+/// A vertical blend from the horizon color straight down to the zenith color
+/// straight up, the sky from the book.
+#[derive(Debug)]
+pub struct SkyGradient {
+    horizon: Color,
+    zenith: Color,
+}
+
+impl SkyGradient {
+    pub fn new(horizon: Color, zenith: Color) -> Arc<Self> {
+        Arc::new(SkyGradient { horizon, zenith })
+    }
+
+    /// The white to light blue sky from the book.
+    pub fn book() -> Arc<Self> {
+        SkyGradient::new(Color::new(1.0, 1.0, 1.0), Color::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Environment for SkyGradient {
+    fn value(&self, direction: &Vec3) -> Color {
+        let unit_direction = Vec3::unit_vector(*direction);
+        let t = 0.5 * (unit_direction.y() + 1.0);
+        (1.0 - t) * self.horizon + t * self.zenith
+    }
+}
+
+/// An environment map stored as a latitude-longitude image, with the top row
+/// straight up and the left edge towards -x, matching the texture mapping of
+/// spheres.
+#[derive(Debug)]
+pub struct EnvironmentMap {
+    image: Image,
+    intensity: f64,
+    /// Rotation about the y axis, in radians.
+    rotation: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Create an environment map scaled by intensity and rotated by the given
+    /// number of degrees about the y axis.
+    pub fn new(image: Image, intensity: f64, rotation: f64) -> Arc<Self> {
+        // Pixels are sampled by their luminance and the solid angle they
+        // cover, which shrinks towards the poles.
+        let height = image.height();
+        let weights: Vec<f64> = image
+            .pixels()
+            .chunks(image.width())
+            .enumerate()
+            .flat_map(|(j, row)| {
+                let sin_theta = f64::sin(utility::PI * (j as f64 + 0.5) / height as f64);
+                row.iter()
+                    .map(move |c| f64::max(c.luminance(), 0.0) * sin_theta)
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, image.width(), height);
+
+        Arc::new(EnvironmentMap {
+            image,
+            intensity,
+            rotation: utility::degrees_to_radians(rotation),
+            distribution,
+        })
+    }
+
+    /// Load an environment map from a Radiance .hdr or PFM file.
+    pub fn load(path: &Path, intensity: f64, rotation: f64) -> io::Result<Arc<Self>> {
+        Ok(EnvironmentMap::new(
+            load_hdr_image(path)?,
+            intensity,
+            rotation,
+        ))
+    }
+
+    /// Returns the map coordinates of a direction, with u going around the y
+    /// axis and v from straight up to straight down.
+    fn direction_to_uv(&self, direction: &Vec3) -> (f64, f64) {
+        let d = Vec3::unit_vector(*direction);
+        let phi = f64::atan2(-d.z(), d.x()) + utility::PI - self.rotation;
+        let u = (phi / (2.0 * utility::PI)).rem_euclid(1.0);
+        let v = f64::acos(utility::clamp(d.y(), -1.0, 1.0)) / utility::PI;
+        (u, v)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let phi = 2.0 * utility::PI * u + self.rotation - utility::PI;
+        let theta = utility::PI * v;
+        Vec3::new(
+            phi.cos() * theta.sin(),
+            theta.cos(),
+            -phi.sin() * theta.sin(),
+        )
+    }
+
+    /// Returns the image pixel seen in a direction.
+    fn pixel(&self, direction: &Vec3) -> (usize, usize) {
+        let (u, v) = self.direction_to_uv(direction);
+
+        let width = self.image.width();
+        let height = self.image.height();
+        (
+            usize::min((u * width as f64) as usize, width - 1),
+            usize::min((v * height as f64) as usize, height - 1),
+        )
+    }
+
+    /// Convert a density over the map coordinates to one over solid angle.
+    fn solid_angle_pdf(uv_pdf: f64, v: f64) -> f64 {
+        let sin_theta = f64::sin(utility::PI * v);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        uv_pdf / (2.0 * utility::PI * utility::PI * sin_theta)
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn value(&self, direction: &Vec3) -> Color {
+        let (i, j) = self.pixel(direction);
+        self.intensity * self.image.pixels()[j * self.image.width() + i]
+    }
+
+    fn sample(&self, rng: &mut Rng) -> Option<(Vec3, f64)> {
+        let (u, v, uv_pdf) = self.distribution.sample(rng)?;
+        let pdf = EnvironmentMap::solid_angle_pdf(uv_pdf, v);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((self.uv_to_direction(u, v), pdf))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        EnvironmentMap::solid_angle_pdf(self.distribution.pdf(u, v), v)
+    }
+}
+
+/// A piecewise constant distribution over [0, 1), with one piece per weight.
+#[derive(Debug)]
+struct Distribution1D {
+    weights: Vec<f64>,
+    /// The running sum of the weights, normalized to end at 1.
+    cdf: Vec<f64>,
+    total: f64,
+}
+
+impl Distribution1D {
+    fn new(weights: &[f64]) -> Self {
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        let mut sum = 0.0;
+        cdf.push(0.0);
+        for weight in weights {
+            sum += weight;
+            cdf.push(sum);
+        }
+        if sum > 0.0 {
+            for value in cdf.iter_mut() {
+                *value /= sum;
+            }
+        }
+
+        Distribution1D {
+            weights: weights.to_vec(),
+            cdf,
+            total: sum,
+        }
+    }
+
+    /// Returns a value in [0, 1), its density, and the piece it is in.
+    fn sample(&self, xi: f64) -> (f64, f64, usize) {
+        // The last piece whose cdf starts at or before xi, skipping pieces
+        // with no weight.
+        let index = self.cdf[1..].partition_point(|&c| c <= xi);
+        let index = usize::min(index, self.weights.len() - 1);
+
+        let start = self.cdf[index];
+        let width = self.cdf[index + 1] - start;
+        let offset = if width > 0.0 {
+            (xi - start) / width
+        } else {
+            0.5
+        };
+
+        let x = (index as f64 + offset) / self.weights.len() as f64;
+        (x, self.pdf(index), index)
+    }
+
+    fn pdf(&self, index: usize) -> f64 {
+        if self.total <= 0.0 {
+            return 0.0;
+        }
+        self.weights[index] * self.weights.len() as f64 / self.total
+    }
+}
+
+/// A piecewise constant distribution over [0, 1)², sampled by choosing a row
+/// and then a column within it.
+#[derive(Debug)]
+struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Create a distribution from row-major weights, with rows along v.
+    fn new(weights: &[f64], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1D> = weights.chunks(width).map(Distribution1D::new).collect();
+        let row_totals: Vec<f64> = rows.iter().map(|row| row.total).collect();
+        assert_eq!(rows.len(), height);
+
+        Distribution2D {
+            rows,
+            marginal: Distribution1D::new(&row_totals),
+        }
+    }
+
+    /// Returns u, v and the density of choosing them, or None if all the
+    /// weights are zero.
+    fn sample(&self, rng: &mut Rng) -> Option<(f64, f64, f64)> {
+        if self.marginal.total <= 0.0 {
+            return None;
+        }
+
+        let (v, v_pdf, row) = self.marginal.sample(rng.random_f64());
+        let (u, u_pdf, _) = self.rows[row].sample(rng.random_f64());
+        Some((u, v, u_pdf * v_pdf))
+    }
+
+    fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = usize::min((v * self.rows.len() as f64) as usize, self.rows.len() - 1);
+        let columns = self.rows[row].weights.len();
+        let column = usize::min((u * columns as f64) as usize, columns - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
+
+/// The Preetham analytic daylight model: a clear sky for a given sun position
+/// and atmospheric turbidity, plus the disc of the sun itself.
+///
+/// The sky is scaled so its luminance straight up is `intensity`, and the sun
+/// is `sun_intensity` times brighter than that. Directions below the horizon
+/// see the sky at the horizon.
+#[derive(Debug)]
+pub struct SunSky {
+    sun_direction: Vec3,
+    /// Cosine of the angular radius of the sun.
+    sun_cos_radius: f64,
+    /// The directions covered by the sun.
+    sun_pdf: ConePdf,
+    sun_color: Color,
+    theta_sun: f64,
+    intensity: f64,
+    perez_y: [f64; 5],
+    perez_x: [f64; 5],
+    perez_yc: [f64; 5],
+    zenith: (f64, f64, f64),
+}
+
+impl SunSky {
+    /// Create a sky with the sun in the given direction. Turbidity ranges from
+    /// about 2 for a very clear sky to 10 for haze, and the sun size is its
+    /// angular diameter in degrees.
+    pub fn new(
+        sun_direction: Vec3,
+        turbidity: f64,
+        intensity: f64,
+        sun_intensity: f64,
+        sun_size: f64,
+    ) -> Arc<Self> {
+        let sun_direction = Vec3::unit_vector(sun_direction);
+        let t = turbidity;
+        // Keep the sun just above the horizon, where the model is defined.
+        let theta_sun = f64::min(f64::acos(sun_direction.y()), utility::PI / 2.0 - 0.001);
+
+        // Distribution coefficients for luminance and chromaticity.
+        let perez_y = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_yc = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        // Zenith luminance and chromaticity.
+        let chi = (4.0 / 9.0 - t / 120.0) * (utility::PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let polynomial = |c: [f64; 4]| {
+            c[0] * theta_sun.powi(3) + c[1] * theta_sun.powi(2) + c[2] * theta_sun + c[3]
+        };
+        let zenith_x = t * t * polynomial([0.00166, -0.00375, 0.00209, 0.0])
+            + t * polynomial([-0.02903, 0.06377, -0.03202, 0.00394])
+            + polynomial([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * polynomial([0.00275, -0.00610, 0.00317, 0.0])
+            + t * polynomial([-0.04214, 0.08970, -0.04153, 0.00516])
+            + polynomial([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let sun_cos_radius = f64::cos(utility::degrees_to_radians(sun_size / 2.0));
+        let mut sky = SunSky {
+            sun_direction,
+            sun_cos_radius,
+            sun_pdf: ConePdf::new(&sun_direction, sun_cos_radius),
+            sun_color: Color::new(0.0, 0.0, 0.0),
+            theta_sun,
+            intensity,
+            perez_y,
+            perez_x,
+            perez_yc,
+            zenith: (zenith_luminance, zenith_x, zenith_y),
+        };
+
+        // The sun has the chromaticity of the sky around it.
+        let (_, x, y) = sky.sky_xyy(&sun_direction);
+        sky.sun_color = sun_intensity * intensity * xyy_to_rgb(x, y, 1.0);
+
+        Arc::new(sky)
+    }
+
+    /// The Perez distribution for a view zenith angle theta and angle gamma
+    /// from the sun.
+    fn perez(coefficients: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = *coefficients;
+        (1.0 + a * f64::exp(b / cos_theta))
+            * (1.0 + c * f64::exp(d * gamma) + e * gamma.cos() * gamma.cos())
+    }
+
+    /// Returns the sky luminance relative to the zenith, and the chromaticity,
+    /// for a unit direction.
+    fn sky_xyy(&self, direction: &Vec3) -> (f64, f64, f64) {
+        let cos_theta = f64::max(direction.y(), 0.001);
+        let cos_gamma = utility::clamp(Vec3::dot(direction, &self.sun_direction), -1.0, 1.0);
+        let gamma = cos_gamma.acos();
+
+        let relative = |coefficients: &[f64; 5], zenith: f64| {
+            zenith * SunSky::perez(coefficients, cos_theta, gamma)
+                / SunSky::perez(coefficients, 1.0, self.theta_sun)
+        };
+
+        (
+            relative(&self.perez_y, 1.0),
+            relative(&self.perez_x, self.zenith.1),
+            relative(&self.perez_yc, self.zenith.2),
+        )
+    }
+}
+
+/// Convert a CIE xyY color to linear sRGB.
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let cx = x * luminance / y;
+    let cz = (1.0 - x - y) * luminance / y;
+    let cy = luminance;
+    Color::new(
+        f64::max(3.2406 * cx - 1.5372 * cy - 0.4986 * cz, 0.0),
+        f64::max(-0.9689 * cx + 1.8758 * cy + 0.0415 * cz, 0.0),
+        f64::max(0.0557 * cx - 0.2040 * cy + 1.0570 * cz, 0.0),
+    )
+}
+
+impl Environment for SunSky {
+    fn value(&self, direction: &Vec3) -> Color {
+        let d = Vec3::unit_vector(*direction);
+        if Vec3::dot(&d, &self.sun_direction) >= self.sun_cos_radius {
+            return self.sun_color;
+        }
+
+        let (luminance, x, y) = self.sky_xyy(&d);
+        self.intensity * xyy_to_rgb(x, y, luminance)
+    }
+
+    /// Half the samples go towards the disc of the sun, the rest are spread
+    /// evenly over the sky.
+    fn sample(&self, rng: &mut Rng) -> Option<(Vec3, f64)> {
+        let pdf = MixturePdf::new(&self.sun_pdf, &SpherePdf);
+        let direction = Vec3::unit_vector(pdf.generate(rng));
+        Some((direction, pdf.value(&direction)))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        MixturePdf::new(&self.sun_pdf, &SpherePdf).value(direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::environment::{Environment, EnvironmentMap, SunSky};
+    use crate::output::Image;
+    use crate::random::Rng;
+    use crate::vec3::{Color, Vec3};
+
+    #[test]
+    fn environment_map_directions() {
+        // Four columns, with the top and bottom rows different colors.
+        let pixels = (0..8)
+            .map(|i| Color::new((i % 4) as f64, (i / 4) as f64, 0.0))
+            .collect();
+        let map = EnvironmentMap::new(Image::new(4, 2, pixels), 1.0, 0.0);
+
+        let up = map.value(&Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(up.y(), 0.0);
+        let down = map.value(&Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(down.y(), 1.0);
+
+        let column = |x, z| map.value(&Vec3::new(x, 0.5, z)).x();
+        assert_eq!(column(-1.0, 0.01), 0.0);
+        assert_eq!(column(0.0, 1.0), 1.0);
+        assert_eq!(column(1.0, -0.01), 2.0);
+        assert_eq!(column(0.0, -1.0), 3.0);
+    }
+
+    #[test]
+    fn environment_map_sampling_matches_pdf() {
+        // A dark map with one bright pixel, rotated so directions don't line
+        // up with the axes.
+        let mut pixels = vec![Color::new(0.1, 0.1, 0.1); 8 * 4];
+        pixels[8 + 5] = Color::new(100.0, 50.0, 20.0);
+        let map = EnvironmentMap::new(Image::new(8, 4, pixels), 1.0, 30.0);
+
+        let mut rng = Rng::new(7);
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let (direction, pdf) = map.sample(&mut rng).unwrap();
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            assert!((map.pdf(&direction) - pdf).abs() < 1e-6 * pdf);
+            if map.value(&direction).x() > 1.0 {
+                bright += 1;
+            }
+        }
+        assert!(bright > 900, "{}", bright);
+
+        // The pdf integrates to one over the sphere.
+        let n = 200;
+        let mut integral = 0.0;
+        for j in 0..n {
+            let theta = crate::utility::PI * (j as f64 + 0.5) / n as f64;
+            for i in 0..2 * n {
+                let phi = crate::utility::PI * (i as f64 + 0.5) / n as f64;
+                let d = Vec3::new(
+                    phi.cos() * theta.sin(),
+                    theta.cos(),
+                    phi.sin() * theta.sin(),
+                );
+                let area = (crate::utility::PI / n as f64).powi(2) * theta.sin();
+                integral += map.pdf(&d) * area;
+            }
+        }
+        assert!((integral - 1.0).abs() < 0.02, "{}", integral);
+    }
+
+    #[test]
+    fn sun_sky_is_blue_and_brightest_at_the_sun() {
+        let sun = Vec3::new(0.0, 0.5, -1.0);
+        let sky = SunSky::new(sun, 3.0, 1.0, 100.0, 2.0);
+
+        let zenith = sky.value(&Vec3::new(0.0, 1.0, 0.0));
+        assert!((zenith.y() - 1.0).abs() < 0.2, "{:?}", zenith);
+        assert!(zenith.z() > zenith.x(), "{:?}", zenith);
+
+        let near_sun = sky.value(&Vec3::new(0.0, 0.55, -1.0));
+        let away = sky.value(&Vec3::new(0.0, 0.5, 1.0));
+        assert!(near_sun.y() > away.y());
+        assert!(sky.value(&sun).y() > 50.0);
+    }
+}
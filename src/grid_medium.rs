@@ -0,0 +1,506 @@
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::{Material, ScatterRecord};
+use crate::random::Rng;
+use crate::ray::Ray;
+use crate::utility;
+use crate::vec3::{Color, Point3, Vec3};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::{Add, Mul};
+use std::path::Path;
+use std::sync::Arc;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The most voxels a grid read from a file may have, to keep a corrupt header
+/// from asking for an enormous grid.
+const MAX_VOXELS: usize = 1 << 28;
+
+/// A dense grid of voxels, each with a density and optionally an emitted
+/// color. Voxels are stored with x changing fastest, then y, then z.
+#[derive(Debug)]
+pub struct VoxelGrid {
+    size: [usize; 3],
+    density: Vec<f64>,
+    emission: Option<Vec<Color>>,
+}
+
+impl VoxelGrid {
+    pub fn new(size: [usize; 3], density: Vec<f64>, emission: Option<Vec<Color>>) -> Self {
+        let count = size[0] * size[1] * size[2];
+        assert!(count > 0);
+        assert_eq!(density.len(), count);
+        if let Some(emission) = &emission {
+            assert_eq!(emission.len(), count);
+        }
+
+        VoxelGrid {
+            size,
+            density,
+            emission,
+        }
+    }
+
+    /// Load a grid from an NRRD file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        read_nrrd(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn max_density(&self) -> f64 {
+        self.density.iter().cloned().fold(0.0, f64::max)
+    }
+
+    /// The density at a point given in grid coordinates, from (0, 0, 0) at
+    /// the first corner of the grid to (1, 1, 1) at the opposite one.
+    pub fn density(&self, p: &Point3) -> f64 {
+        self.interpolate(p, |i| self.density[i])
+    }
+
+    /// The emitted color at a point in grid coordinates.
+    pub fn emission(&self, p: &Point3) -> Color {
+        match &self.emission {
+            Some(emission) => self.interpolate(p, |i| emission[i]),
+            None => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// The indices of the voxel centers on either side of a point in grid
+    /// coordinates along each axis, and how far the point is between them.
+    fn neighbours(&self, p: &Point3) -> ([(usize, usize); 3], [f64; 3]) {
+        let mut index = [(0, 0); 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let n = self.size[axis];
+            let x = utility::clamp(p[axis] * n as f64 - 0.5, 0.0, (n - 1) as f64);
+            let i = x.floor() as usize;
+            index[axis] = (i, usize::min(i + 1, n - 1));
+            fraction[axis] = x - i as f64;
+        }
+
+        (index, fraction)
+    }
+
+    /// Trilinear interpolation between the values at the voxel centers.
+    fn interpolate<T>(&self, p: &Point3, value: impl Fn(usize) -> T) -> T
+    where
+        T: Add<Output = T>,
+        f64: Mul<T, Output = T>,
+    {
+        let (index, fraction) = self.neighbours(p);
+        let voxel = |x: usize, y: usize, z: usize| value(x + self.size[0] * (y + self.size[1] * z));
+        let lerp = |f: f64, a: T, b: T| (1.0 - f) * a + f * b;
+        let [(x0, x1), (y0, y1), (z0, z1)] = index;
+        let [fx, fy, fz] = fraction;
+
+        let y_lerp = |z| {
+            lerp(
+                fy,
+                lerp(fx, voxel(x0, y0, z), voxel(x1, y0, z)),
+                lerp(fx, voxel(x0, y1, z), voxel(x1, y1, z)),
+            )
+        };
+        lerp(fz, y_lerp(z0), y_lerp(z1))
+    }
+}
+
+/// The sample types supported in NRRD files.
+#[derive(Debug, Clone, Copy)]
+enum SampleType {
+    U8,
+    F32,
+    F64,
+}
+
+/// Read a grid from an NRRD file with the data following the header in the
+/// same file. The grid is 3 dimensional with a density per voxel, or 4
+/// dimensional with the first axis holding either just a density or a
+/// density followed by an emitted color. 8 bit samples are scaled to [0, 1].
+pub fn read_nrrd(reader: &mut dyn BufRead) -> io::Result<VoxelGrid> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("NRRD") {
+        return Err(invalid_data("not an NRRD file".to_string()));
+    }
+
+    let mut sample_type = None;
+    let mut dimension = None;
+    let mut sizes = None;
+    let mut encoding = None;
+    let mut big_endian = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("unexpected end of NRRD header".to_string()));
+        }
+        let line = line.trim_end();
+        // The header ends at the first empty line.
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        // Key value pairs (written with ":=") are ignored, as are any fields
+        // that don't change how the data is read.
+        let (field, value) = match line.find(": ") {
+            Some(index) if !line[..index].ends_with(':') => (&line[..index], &line[index + 2..]),
+            _ => continue,
+        };
+        match field {
+            "type" => {
+                sample_type = Some(match value {
+                    "uchar" | "unsigned char" | "uint8" | "uint8_t" => SampleType::U8,
+                    "float" => SampleType::F32,
+                    "double" => SampleType::F64,
+                    other => {
+                        return Err(invalid_data(format!("unsupported NRRD type '{}'", other)))
+                    }
+                })
+            }
+            "dimension" => {
+                dimension = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| invalid_data(format!("invalid NRRD dimension '{}'", value)))?,
+                )
+            }
+            "sizes" => {
+                let parsed: Result<Vec<usize>, _> =
+                    value.split_whitespace().map(|s| s.parse()).collect();
+                sizes = Some(
+                    parsed.map_err(|_| invalid_data(format!("invalid NRRD sizes '{}'", value)))?,
+                );
+            }
+            "encoding" => {
+                encoding = Some(match value {
+                    "raw" => false,
+                    "ascii" | "text" | "txt" => true,
+                    other => {
+                        return Err(invalid_data(format!(
+                            "unsupported NRRD encoding '{}'",
+                            other
+                        )))
+                    }
+                })
+            }
+            "endian" => big_endian = value == "big",
+            "data file" | "datafile" => {
+                return Err(invalid_data(
+                    "NRRD files with detached data are not supported".to_string(),
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let missing = |field: &str| invalid_data(format!("NRRD header is missing '{}'", field));
+    let sample_type = sample_type.ok_or_else(|| missing("type"))?;
+    let sizes = sizes.ok_or_else(|| missing("sizes"))?;
+    let ascii = encoding.ok_or_else(|| missing("encoding"))?;
+    let (channels, size) = match (dimension, sizes.as_slice()) {
+        (Some(3), &[x, y, z]) => (1, [x, y, z]),
+        (Some(4), &[c, x, y, z]) if c == 1 || c == 4 => (c, [x, y, z]),
+        _ => {
+            return Err(invalid_data(
+                "NRRD grids must have 3 dimensions, or 4 with 1 or 4 channels".to_string(),
+            ))
+        }
+    };
+    let count = size[0]
+        .checked_mul(size[1])
+        .and_then(|count| count.checked_mul(size[2]))
+        .filter(|&count| count > 0 && count <= MAX_VOXELS)
+        .ok_or_else(|| {
+            invalid_data(format!(
+                "invalid NRRD grid size {}x{}x{}",
+                size[0], size[1], size[2]
+            ))
+        })?;
+
+    let values: Vec<f64> = if ascii {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let parsed: Result<Vec<f64>, _> = text
+            .split_whitespace()
+            .take(count * channels)
+            .map(|s| s.parse())
+            .collect();
+        parsed.map_err(|_| invalid_data("invalid NRRD sample".to_string()))?
+    } else {
+        let bytes = match sample_type {
+            SampleType::U8 => 1,
+            SampleType::F32 => 4,
+            SampleType::F64 => 8,
+        };
+        // The data is read as it arrives rather than allocated up front, so a
+        // short file can't ask for a huge buffer.
+        let length = count * channels * bytes;
+        let mut data = Vec::new();
+        reader.take(length as u64).read_to_end(&mut data)?;
+        if data.len() < length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "NRRD data is shorter than its size",
+            ));
+        }
+        data.chunks(bytes)
+            .map(|b| match sample_type {
+                SampleType::U8 => b[0] as f64,
+                SampleType::F32 => {
+                    let b = [b[0], b[1], b[2], b[3]];
+                    if big_endian {
+                        f32::from_be_bytes(b) as f64
+                    } else {
+                        f32::from_le_bytes(b) as f64
+                    }
+                }
+                SampleType::F64 => {
+                    let b = [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]];
+                    if big_endian {
+                        f64::from_be_bytes(b)
+                    } else {
+                        f64::from_le_bytes(b)
+                    }
+                }
+            })
+            .collect()
+    };
+    if values.len() < count * channels {
+        return Err(invalid_data("unexpected end of NRRD data".to_string()));
+    }
+
+    let scale = match sample_type {
+        SampleType::U8 => 1.0 / 255.0,
+        _ => 1.0,
+    };
+    let density: Vec<f64> = values.iter().step_by(channels).map(|d| scale * d).collect();
+    if density.iter().any(|d| !d.is_finite() || *d < 0.0) {
+        return Err(invalid_data(
+            "NRRD densities must be finite and not negative".to_string(),
+        ));
+    }
+    let emission = if channels == 4 {
+        let colors = values
+            .chunks(4)
+            .map(|v| scale * Color::new(v[1], v[2], v[3]))
+            .collect();
+        Some(colors)
+    } else {
+        None
+    };
+
+    Ok(VoxelGrid::new(size, density, emission))
+}
+
+/// The material at points inside a grid medium. It scatters like the phase
+/// function and emits the grid's emission at the point.
+struct GridMaterial {
+    grid: VoxelGrid,
+    bbox: Aabb,
+    emission_scale: f64,
+    phase_function: Arc<dyn Material + Send + Sync>,
+}
+
+impl GridMaterial {
+    /// Convert a point in the world to grid coordinates.
+    fn to_grid(&self, p: &Point3) -> Point3 {
+        let min = self.bbox.min();
+        let extent = self.bbox.max() - min;
+        Point3::new(
+            (p.x() - min.x()) / extent.x(),
+            (p.y() - min.y()) / extent.y(),
+            (p.z() - min.z()) / extent.z(),
+        )
+    }
+}
+
+impl Material for GridMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<ScatterRecord> {
+        self.phase_function.scatter(r_in, rec, rng)
+    }
+
+    fn emitted(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        self.emission_scale * self.grid.emission(&self.to_grid(p))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.phase_function.scattering_pdf(r_in, rec, scattered)
+    }
+}
+
+/// A volume with its density and emission given by a voxel grid stretched
+/// over an axis aligned box.
+///
+/// Rays are traced through it by delta tracking: collisions are sampled
+/// as if the whole box had the maximum density of the grid, and each one is
+/// kept as a real scattering with the probability of the actual density
+/// over that maximum. The emission is per unit of density, so it is added
+/// at each scattering.
+pub struct GridMedium {
+    material: Arc<GridMaterial>,
+    density_scale: f64,
+    /// The maximum density anywhere in the grid.
+    majorant: f64,
+}
+
+impl GridMedium {
+    pub fn new(
+        grid: VoxelGrid,
+        min: Point3,
+        max: Point3,
+        density_scale: f64,
+        emission_scale: f64,
+        phase_function: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        let majorant = density_scale * grid.max_density();
+        GridMedium {
+            material: Arc::new(GridMaterial {
+                grid,
+                bbox: Aabb::new(min, max),
+                emission_scale,
+                phase_function,
+            }),
+            density_scale,
+            majorant,
+        }
+    }
+
+    pub fn new_arc(
+        grid: VoxelGrid,
+        min: Point3,
+        max: Point3,
+        density_scale: f64,
+        emission_scale: f64,
+        phase_function: Arc<dyn Material + Send + Sync>,
+    ) -> Arc<Self> {
+        Arc::new(GridMedium::new(
+            grid,
+            min,
+            max,
+            density_scale,
+            emission_scale,
+            phase_function,
+        ))
+    }
+}
+
+impl Hittable for GridMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        let (t_enter, t_leave) = self.material.bbox.hit_interval(r, t_min, t_max)?;
+
+        let ray_length = r.direction().length();
+        let mut t = t_enter;
+        loop {
+            t -= (1.0 - rng.random_f64()).ln() / (self.majorant * ray_length);
+            if t >= t_leave {
+                return None;
+            }
+
+            let p = r.at(t);
+            let density =
+                self.density_scale * self.material.grid.density(&self.material.to_grid(&p));
+            if rng.random_f64() * self.majorant < density {
+                return Some(HitRecord::new(
+                    p,
+                    r,
+                    Vec3::new(1.0, 0.0, 0.0),
+                    t,
+                    0.0,
+                    0.0,
+                    self.material.clone(),
+                ));
+            }
+        }
+    }
+
+    fn aabb(&self) -> Option<Aabb> {
+        Some(self.material.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid_medium::{read_nrrd, GridMedium, VoxelGrid};
+    use crate::hit::Hittable;
+    use crate::material::Isotropic;
+    use crate::random::Rng;
+    use crate::ray::Ray;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    #[test]
+    fn read_ascii_and_raw_nrrd() {
+        let ascii = "NRRD0004\n# A comment\ntype: float\ndimension: 4\nsizes: 4 2 1 1\n\
+                     encoding: ascii\nspace directions: none (1,0,0) (0,1,0) (0,0,1)\n\n\
+                     0.5 1 2 3\n1.5 0 0 0\n";
+        let grid = read_nrrd(&mut ascii.as_bytes()).unwrap();
+        assert_eq!(grid.size, [2, 1, 1]);
+        assert_eq!(grid.density, vec![0.5, 1.5]);
+        assert_eq!(
+            *grid.emission(&Point3::new(0.0, 0.5, 0.5)),
+            *Color::new(1.0, 2.0, 3.0)
+        );
+        assert_eq!(grid.density(&Point3::new(0.5, 0.5, 0.5)), 1.0);
+
+        let mut raw =
+            b"NRRD0004\ntype: uchar\ndimension: 3\nsizes: 1 2 1\nencoding: raw\n\n".to_vec();
+        raw.extend_from_slice(&[0, 255]);
+        let grid = read_nrrd(&mut raw.as_slice()).unwrap();
+        assert_eq!(grid.density, vec![0.0, 1.0]);
+        assert!(grid.emission.is_none());
+
+        let truncated =
+            "NRRD0004\ntype: float\ndimension: 3\nsizes: 2 2 2\nencoding: ascii\n\n1 2 3\n";
+        assert!(read_nrrd(&mut truncated.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn reject_empty_and_huge_nrrd() {
+        let header = |sizes: &str| {
+            format!(
+                "NRRD0004\ntype: float\ndimension: 3\nsizes: {}\nencoding: raw\n\n",
+                sizes
+            )
+        };
+        assert!(read_nrrd(&mut header("0 1 1").as_bytes()).is_err());
+        assert!(read_nrrd(&mut header("4294967296 4294967296 4294967296").as_bytes()).is_err());
+        assert!(read_nrrd(&mut header("16384 16384 16384").as_bytes()).is_err());
+        assert!(read_nrrd(&mut header("2 1 1").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn delta_tracking_matches_transmittance() {
+        let mut rng = Rng::new(0);
+        // The density is 1 at the center of the first voxel and falls to 0 at
+        // the center of the second, so along the x axis of the 2 unit wide
+        // box the optical depth is 1.
+        let grid = VoxelGrid::new([2, 1, 1], vec![1.0, 0.0], None);
+        let medium = GridMedium::new(
+            grid,
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 1.0, 1.0),
+            1.0,
+            1.0,
+            Isotropic::new(Color::new(1.0, 1.0, 1.0)),
+        );
+
+        let r = Ray::new(Point3::new(-1.0, 0.5, 0.5), Vec3::new(0.5, 0.0, 0.0), 0.0);
+        let count = 100_000;
+        let passed = (0..count)
+            .filter(|_| medium.hit(&r, 0.001, f64::INFINITY, &mut rng).is_none())
+            .count();
+
+        let transmittance = passed as f64 / count as f64;
+        assert!(
+            (transmittance - f64::exp(-1.0)).abs() < 0.01,
+            "{}",
+            transmittance
+        );
+    }
+}
@@ -0,0 +1,609 @@
+//! Readers and writers for high dynamic range image formats. These store the
+//! linear colors of the image as is, without any clamping or gamma correction.
+
+use crate::output::Image;
+use crate::vec3::Color;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// Write a little endian PFM image. PFM stores scanlines from the bottom up.
+pub fn write_pfm(image: &Image, writer: &mut dyn Write) -> io::Result<()> {
+    // A negative scale indicates little endian data.
+    write!(writer, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+
+    for row in image.pixels().chunks(image.width()).rev() {
+        for color in row {
+            for component in [color.x(), color.y(), color.z()].iter() {
+                writer.write_all(&(*component as f32).to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert a color to Radiance's shared exponent RGBE representation.
+pub fn color_to_rgbe(color: Color) -> [u8; 4] {
+    let v = f64::max(color.x(), f64::max(color.y(), color.z()));
+    if v.is_nan() || v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // Split v into a mantissa in [0.5, 1) and an exponent, like frexp. Colors
+    // too bright for the largest exponent, infinite ones included, saturate at
+    // a mantissa of 255 with it.
+    const MAX_EXPONENT: i32 = 127;
+    let mut exponent = MAX_EXPONENT;
+    if v.is_finite() {
+        exponent = v.log2().floor() as i32 + 1;
+        if v / f64::powi(2.0, exponent) >= 1.0 {
+            exponent += 1;
+        }
+        exponent = i32::min(exponent, MAX_EXPONENT);
+    }
+    let scale = 256.0 / f64::powi(2.0, exponent);
+
+    let channel = |x: f64| f64::min(f64::max(x, 0.0) * scale, 255.0) as u8;
+    [
+        channel(color.x()),
+        channel(color.y()),
+        channel(color.z()),
+        (exponent + 128) as u8,
+    ]
+}
+
+/// Write a Radiance .hdr image, using run length encoded scanlines where the
+/// format allows it.
+pub fn write_radiance_hdr(image: &Image, writer: &mut dyn Write) -> io::Result<()> {
+    write!(
+        writer,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        image.height(),
+        image.width()
+    )?;
+
+    let width = image.width();
+    for row in image.pixels().chunks(width) {
+        let rgbe: Vec<[u8; 4]> = row.iter().map(|c| color_to_rgbe(*c)).collect();
+
+        // Run length encoding is only defined for these widths, otherwise the
+        // scanline is written flat.
+        if !(8..=0x7fff).contains(&width) {
+            for pixel in rgbe.iter() {
+                writer.write_all(pixel)?;
+            }
+            continue;
+        }
+
+        writer.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        for channel in 0..4 {
+            let values: Vec<u8> = rgbe.iter().map(|pixel| pixel[channel]).collect();
+            write_rle_channel(&values, writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write one channel of a scanline as a series of runs (count > 128, then the
+/// repeated value) and literal dumps (count <= 128, then the values).
+fn write_rle_channel(values: &[u8], writer: &mut dyn Write) -> io::Result<()> {
+    const MIN_RUN: usize = 4;
+    const MAX_COUNT: usize = 127;
+
+    let mut i = 0;
+    while i < values.len() {
+        // Find the next run long enough to be worth encoding.
+        let mut run_start = i;
+        let mut run_length = 0;
+        while run_start < values.len() {
+            run_length = values[run_start..]
+                .iter()
+                .take(MAX_COUNT)
+                .take_while(|&&v| v == values[run_start])
+                .count();
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+        if run_length < MIN_RUN {
+            run_start = values.len();
+        }
+
+        // Dump everything up to the run as literals.
+        while i < run_start {
+            let count = usize::min(run_start - i, 128);
+            writer.write_all(&[count as u8])?;
+            writer.write_all(&values[i..i + count])?;
+            i += count;
+        }
+
+        if run_start < values.len() {
+            writer.write_all(&[128 + run_length as u8, values[run_start]])?;
+            i = run_start + run_length;
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The most pixels an image read from a file may have, to keep a corrupt
+/// header from asking for an enormous image.
+const MAX_PIXELS: usize = 1 << 28;
+
+/// The number of pixels in an image read from a file, or an error if it is
+/// empty or too large.
+pub fn checked_pixel_count(width: usize, height: usize) -> io::Result<usize> {
+    width
+        .checked_mul(height)
+        .filter(|&count| count > 0 && count <= MAX_PIXELS)
+        .ok_or_else(|| invalid_data(format!("invalid image size {}x{}", width, height)))
+}
+
+/// Load a Radiance .hdr or PFM image, chosen by the file extension.
+pub fn load_hdr_image(path: &Path) -> io::Result<Image> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let mut reader = BufReader::new(File::open(path)?);
+
+    match extension.as_deref() {
+        Some("hdr") => read_radiance_hdr(&mut reader),
+        Some("pfm") => read_pfm(&mut reader),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "high dynamic range images must be .hdr or .pfm files",
+        )),
+    }
+}
+
+/// Read the next whitespace separated token of a PFM header.
+fn read_pfm_token(reader: &mut dyn BufRead) -> io::Result<String> {
+    let mut token = String::new();
+    let mut byte = [0];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Err(invalid_data("unexpected end of PFM header".to_string()));
+        }
+        if !byte[0].is_ascii_whitespace() {
+            token.push(byte[0] as char);
+        } else if !token.is_empty() {
+            // The single whitespace after the last token is consumed here,
+            // leaving the reader at the start of the data.
+            return Ok(token);
+        }
+    }
+}
+
+/// Read a color (PF) or grayscale (Pf) PFM image.
+pub fn read_pfm(reader: &mut dyn BufRead) -> io::Result<Image> {
+    let channels = match read_pfm_token(reader)?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        other => return Err(invalid_data(format!("unsupported PFM type '{}'", other))),
+    };
+
+    let mut dimension = || -> io::Result<usize> {
+        let token = read_pfm_token(reader)?;
+        token
+            .parse()
+            .map_err(|_| invalid_data(format!("invalid PFM dimension '{}'", token)))
+    };
+    let width = dimension()?;
+    let height = dimension()?;
+
+    let token = read_pfm_token(reader)?;
+    let scale: f32 = token
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid PFM scale '{}'", token)))?;
+
+    // The data is read as it arrives rather than allocated up front, so a
+    // short file can't ask for a huge buffer.
+    let length = checked_pixel_count(width, height)? * channels * 4;
+    let mut data = Vec::new();
+    reader.take(length as u64).read_to_end(&mut data)?;
+    if data.len() < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "PFM data is shorter than its size",
+        ));
+    }
+    let values: Vec<f64> = data
+        .chunks(4)
+        .map(|b| {
+            let bytes = [b[0], b[1], b[2], b[3]];
+            // A negative scale indicates little endian data.
+            if scale < 0.0 {
+                f32::from_le_bytes(bytes) as f64
+            } else {
+                f32::from_be_bytes(bytes) as f64
+            }
+        })
+        .collect();
+
+    // Scanlines are stored from the bottom up.
+    let pixels = values
+        .chunks(width * channels)
+        .rev()
+        .flat_map(|row| row.chunks(channels))
+        .map(|pixel| match pixel {
+            [r, g, b] => Color::new(*r, *g, *b),
+            _ => Color::new(pixel[0], pixel[0], pixel[0]),
+        })
+        .collect();
+
+    Ok(Image::new(width, height, pixels))
+}
+
+/// Convert a shared exponent RGBE value back to a color.
+pub fn rgbe_to_color(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let scale = f64::powi(2.0, rgbe[3] as i32 - (128 + 8));
+    Color::new(
+        rgbe[0] as f64 * scale,
+        rgbe[1] as f64 * scale,
+        rgbe[2] as f64 * scale,
+    )
+}
+
+/// Read a Radiance .hdr image, with either flat or run length encoded
+/// scanlines. Only the standard -Y +X orientation is supported.
+pub fn read_radiance_hdr(reader: &mut dyn BufRead) -> io::Result<Image> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid_data("missing Radiance signature".to_string()));
+    }
+
+    // The header is a list of variables ended by an empty line.
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data(
+                "unexpected end of Radiance header".to_string(),
+            ));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data(format!("unsupported Radiance {}", line)));
+        }
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let (height, width) = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["-Y", height, "+X", width] => match (height.parse(), width.parse()) {
+            (Ok(height), Ok(width)) => (height, width),
+            _ => {
+                return Err(invalid_data(format!(
+                    "invalid resolution '{}'",
+                    line.trim()
+                )))
+            }
+        },
+        _ => {
+            return Err(invalid_data(format!(
+                "unsupported resolution '{}'",
+                line.trim()
+            )))
+        }
+    };
+
+    checked_pixel_count(width, height)?;
+    let mut pixels = Vec::new();
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_rgbe_scanline(reader, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|rgbe| rgbe_to_color(*rgbe)));
+    }
+
+    Ok(Image::new(width, height, pixels))
+}
+
+fn read_rgbe_scanline(reader: &mut dyn BufRead, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    let mut start = [0; 4];
+    reader.read_exact(&mut start)?;
+
+    let encoded = (8..=0x7fff).contains(&width)
+        && start[0] == 2
+        && start[1] == 2
+        && ((start[2] as usize) << 8 | start[3] as usize) == width;
+    if !encoded {
+        scanline[0] = start;
+        for pixel in scanline[1..].iter_mut() {
+            reader.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+
+    // Each channel is stored separately as runs and literal dumps.
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0];
+            reader.read_exact(&mut count)?;
+            let (count, run) = match count[0] as usize {
+                c if c > 128 => (c - 128, true),
+                c => (c, false),
+            };
+            if count == 0 || x + count > width {
+                return Err(invalid_data("corrupt run length encoding".to_string()));
+            }
+
+            if run {
+                let mut value = [0];
+                reader.read_exact(&mut value)?;
+                for pixel in scanline[x..x + count].iter_mut() {
+                    pixel[channel] = value[0];
+                }
+            } else {
+                let mut values = vec![0; count];
+                reader.read_exact(&mut values)?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+            }
+            x += count;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write an uncompressed scanline OpenEXR image with 32-bit float R, G and B
+/// channels.
+pub fn write_exr(image: &Image, writer: &mut dyn Write) -> io::Result<()> {
+    let width = image.width() as i32;
+    let height = image.height() as i32;
+
+    let mut header = Vec::new();
+    // Magic number and version 2, single part scanline file.
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    // Channels must be sorted by name.
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"].iter() {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        // Pixel type FLOAT, pLinear and reserved bytes, then x and y sampling.
+        channels.extend_from_slice(&2i32.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    write_exr_attribute(&mut header, "channels", "chlist", &channels);
+
+    write_exr_attribute(&mut header, "compression", "compression", &[0]);
+
+    let mut window = Vec::new();
+    for value in [0, 0, width - 1, height - 1].iter() {
+        window.extend_from_slice(&i32::to_le_bytes(*value));
+    }
+    write_exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_exr_attribute(&mut header, "displayWindow", "box2i", &window);
+
+    write_exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_exr_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    write_exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_exr_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // Each scanline block is its y coordinate, the data size, then each
+    // channel's values for the whole scanline.
+    let line_size = 3 * 4 * image.width();
+    let block_size = 4 + 4 + line_size;
+    let table_size = 8 * image.height();
+
+    writer.write_all(&header)?;
+    for y in 0..image.height() {
+        let offset = (header.len() + table_size + y * block_size) as u64;
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+
+    for (y, row) in image.pixels().chunks(image.width()).enumerate() {
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&(line_size as i32).to_le_bytes())?;
+        for channel in [2, 1, 0].iter() {
+            for color in row {
+                writer.write_all(&(color[*channel] as f32).to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hdr::{
+        color_to_rgbe, read_pfm, read_radiance_hdr, write_exr, write_pfm, write_radiance_hdr,
+        write_rle_channel,
+    };
+    use crate::output::Image;
+    use crate::vec3::Color;
+    use std::collections::HashMap;
+
+    #[test]
+    fn pfm_is_unclamped_and_bottom_up() {
+        let image = Image::new(
+            1,
+            2,
+            vec![Color::new(4.0, 0.5, 0.0), Color::new(0.0, 0.0, 1.0)],
+        );
+        let mut pfm = Vec::new();
+        write_pfm(&image, &mut pfm).unwrap();
+
+        let header = b"PF\n1 2\n-1.0\n";
+        assert_eq!(&pfm[..header.len()], header);
+        let values: Vec<f32> = pfm[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values, vec![0.0, 0.0, 1.0, 4.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn exr_header_and_scanlines() {
+        let image = Image::new(
+            2,
+            1,
+            vec![Color::new(4.0, 0.5, 0.0), Color::new(0.25, 1.0, 2.0)],
+        );
+        let mut exr = Vec::new();
+        write_exr(&image, &mut exr).unwrap();
+        assert_eq!(&exr[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+        // Read the attributes, each a name, a type and a sized value, up to the
+        // empty name that ends the header.
+        let mut attributes = HashMap::new();
+        let mut position = 8;
+        let next_string = |position: &mut usize| {
+            let length = exr[*position..].iter().position(|&b| b == 0).unwrap();
+            let string = String::from_utf8(exr[*position..*position + length].to_vec()).unwrap();
+            *position += length + 1;
+            string
+        };
+        loop {
+            let name = next_string(&mut position);
+            if name.is_empty() {
+                break;
+            }
+            let kind = next_string(&mut position);
+            let b = &exr[position..position + 4];
+            let size = i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+            let value = exr[position + 4..position + 4 + size].to_vec();
+            position += 4 + size;
+            attributes.insert(name, (kind, value));
+        }
+
+        let (kind, channels) = &attributes["channels"];
+        assert_eq!(kind, "chlist");
+        let names: Vec<u8> = channels.chunks(18).map(|channel| channel[0]).collect();
+        assert_eq!(names, b"BGR\0");
+        assert_eq!(
+            attributes["compression"],
+            ("compression".to_string(), vec![0])
+        );
+        let (kind, window) = &attributes["dataWindow"];
+        assert_eq!(kind, "box2i");
+        let window: Vec<i32> = window
+            .chunks(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(window, vec![0, 0, 1, 0]);
+        assert_eq!(attributes["lineOrder"], ("lineOrder".to_string(), vec![0]));
+
+        // The offset table points at the only scanline, just after it.
+        let b = &exr[position..position + 8];
+        let offset = u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]);
+        assert_eq!(offset as usize, position + 8);
+
+        // The scanline's y and data size, then each channel in turn.
+        let values: Vec<[u8; 4]> = exr[position + 8..]
+            .chunks(4)
+            .map(|b| [b[0], b[1], b[2], b[3]])
+            .collect();
+        assert_eq!(values.len(), 2 + 6);
+        assert_eq!(i32::from_le_bytes(values[0]), 0);
+        assert_eq!(i32::from_le_bytes(values[1]), 24);
+        let floats: Vec<f32> = values[2..].iter().map(|b| f32::from_le_bytes(*b)).collect();
+        assert_eq!(floats, vec![0.0, 2.0, 0.5, 1.0, 4.0, 0.25]);
+    }
+
+    #[test]
+    fn rgbe() {
+        assert_eq!(color_to_rgbe(Color::new(0.0, 0.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(color_to_rgbe(Color::new(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(color_to_rgbe(Color::new(0.75, 0.0, 0.0)), [192, 0, 0, 128]);
+        assert_eq!(color_to_rgbe(Color::new(100.0, 0.0, 0.0)), [200, 0, 0, 135]);
+    }
+
+    #[test]
+    fn rgbe_saturates_bright_colors() {
+        let infinity = f64::INFINITY;
+        assert_eq!(
+            color_to_rgbe(Color::new(infinity, 1.0, 0.0)),
+            [255, 0, 0, 255]
+        );
+        assert_eq!(color_to_rgbe(Color::new(1e40, 0.0, 0.0)), [255, 0, 0, 255]);
+        assert_eq!(
+            color_to_rgbe(Color::new(f64::NAN, 1.0, 0.0)),
+            color_to_rgbe(Color::new(0.0, 1.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn rle_runs_and_dumps() {
+        let mut encoded = Vec::new();
+        write_rle_channel(&[1, 2, 5, 5, 5, 5, 5, 3], &mut encoded).unwrap();
+        assert_eq!(encoded, vec![2, 1, 2, 128 + 5, 5, 1, 3]);
+    }
+
+    #[test]
+    fn read_back_written_images() {
+        // Wide enough to use run length encoding for the .hdr file.
+        let pixels: Vec<Color> = (0..20)
+            .map(|i| Color::new(i as f64 / 4.0, 0.5, if i < 10 { 2.0 } else { 0.0 }))
+            .collect();
+        let image = Image::new(10, 2, pixels);
+
+        let mut pfm = Vec::new();
+        write_pfm(&image, &mut pfm).unwrap();
+        let read = read_pfm(&mut pfm.as_slice()).unwrap();
+        assert_eq!((read.width(), read.height()), (10, 2));
+        for (a, b) in image.pixels().iter().zip(read.pixels()) {
+            assert_eq!(**a, **b);
+        }
+
+        let mut hdr = Vec::new();
+        write_radiance_hdr(&image, &mut hdr).unwrap();
+        let read = read_radiance_hdr(&mut hdr.as_slice()).unwrap();
+        assert_eq!((read.width(), read.height()), (10, 2));
+        for (a, b) in image.pixels().iter().zip(read.pixels()) {
+            assert!((**a - **b).length() < 0.02, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn reject_empty_and_huge_images() {
+        assert!(read_pfm(&mut &b"PF\n0 0\n-1.0\n"[..]).is_err());
+        assert!(read_pfm(&mut &b"PF\n4294967296 4294967296\n-1.0\n"[..]).is_err());
+        assert!(read_pfm(&mut &b"PF\n2 1\n-1.0\n\0\0\0\0"[..]).is_err());
+        assert!(read_radiance_hdr(&mut &b"#?RADIANCE\n\n-Y 0 +X 0\n"[..]).is_err());
+        assert!(read_radiance_hdr(&mut &b"#?RADIANCE\n\n-Y 99999999 +X 99999999\n"[..]).is_err());
+    }
+}
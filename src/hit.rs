@@ -1,4 +1,6 @@
+use crate::aabb::Aabb;
 use crate::material::Material;
+use crate::random::Rng;
 use crate::ray::Ray;
 use crate::vec3::{Point3, Vec3};
 use std::sync::Arc;
@@ -9,6 +11,9 @@ pub struct HitRecord {
     pub normal: Vec3,
     pub material: Arc<dyn Material>,
     pub t: f64,
+    /// Surface coordinates of the hit point.
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
 }
 
@@ -22,14 +27,16 @@ impl HitRecord {
     //     }
     // }
 
-    /// Construct a new hit record using the specified point, time, and
-    /// ray and outward normal to calculate the normal and if this hit record
-    /// is facing the front or not.
+    /// Construct a new hit record using the specified point, time, surface
+    /// coordinates, and ray and outward normal to calculate the normal and if
+    /// this hit record is facing the front or not.
     pub fn new(
         p: Point3,
         r: &Ray,
         outward_normal: Vec3,
         t: f64,
+        u: f64,
+        v: f64,
         material: Arc<dyn Material>,
     ) -> Self {
         let front_face = Vec3::dot(&r.direction(), &outward_normal) < 0.0;
@@ -43,6 +50,8 @@ impl HitRecord {
             p,
             normal,
             t,
+            u,
+            v,
             material,
             front_face,
         }
@@ -59,12 +68,32 @@ impl HitRecord {
 }
 
 pub trait Hittable {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    /// Returns the closest hit along the ray between t_min and t_max. The
+    /// random number generator is for objects that are hit at random, like
+    /// volumes.
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord>;
+
+    /// Returns the bounding box of the object, or None if the object has no
+    /// finite bounds (or is empty).
+    fn aabb(&self) -> Option<Aabb>;
+
+    /// Returns the probability density, over solid angle, of `random_point`
+    /// choosing a point in the direction from the origin. Objects that can't
+    /// be sampled return 0.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Choose a random point on the object as seen from the origin, used to
+    /// send rays towards it, or None if the object can't be sampled.
+    fn random_point(&self, _origin: &Point3, _rng: &mut Rng) -> Option<Point3> {
+        None
+    }
 }
 
 impl std::fmt::Debug for dyn Hittable + Send + Sync {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "dyn Hittable")
     }
 }
 
@@ -74,6 +103,7 @@ pub struct HittableList {
 }
 
 impl HittableList {
+    #[allow(dead_code)]
     pub fn clear(&mut self) {
         self.objects.clear();
     }
@@ -81,15 +111,19 @@ impl HittableList {
     pub fn add(&mut self, object: Arc<dyn Hittable + Send + Sync>) {
         self.objects.push(object);
     }
+
+    pub fn objects(&self) -> &[Arc<dyn Hittable + Send + Sync>] {
+        &self.objects
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord> {
         let mut hit = None;
         let mut closest_so_far = t_max;
 
         self.objects.iter().for_each(|object| {
-            if let Some(new_hit) = object.hit(r, t_min, closest_so_far) {
+            if let Some(new_hit) = object.hit(r, t_min, closest_so_far, rng) {
                 closest_so_far = new_hit.t;
                 hit = Some(new_hit);
             }
@@ -97,4 +131,39 @@ impl Hittable for HittableList {
 
         hit
     }
+
+    fn aabb(&self) -> Option<Aabb> {
+        let mut objects = self.objects.iter();
+        let mut output_box = objects.next()?.aabb()?;
+
+        for object in objects {
+            output_box = Aabb::surrounding_box(&output_box, &object.aabb()?);
+        }
+
+        Some(output_box)
+    }
+
+    /// Each object is chosen with equal probability.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random_point(&self, origin: &Point3, rng: &mut Rng) -> Option<Point3> {
+        if self.objects.is_empty() {
+            return None;
+        }
+
+        let count = self.objects.len();
+        let index = usize::min((rng.random_f64() * count as f64) as usize, count - 1);
+        self.objects[index].random_point(origin, rng)
+    }
 }
@@ -1,175 +1,853 @@
+use crate::adaptive::RunningVariance;
+use crate::bvh::BvhNode;
 use crate::camera::Camera;
-use crate::hit::{Hittable, HittableList};
-use crate::material::Material;
-use crate::material::{Dielectric, Lambertian, Metal};
+use crate::checkpoint::Checkpoint;
+use crate::environment::Environment;
+use crate::hit::{HitRecord, Hittable, HittableList};
+use crate::material::ScatterRecord;
+use crate::options::{exit_with_error, Options};
+use crate::output::{write_image, Image, ImageFormat};
+use crate::pdf::{HittablePdf, Pdf};
+use crate::random::Rng;
 use crate::ray::Ray;
-use crate::sphere::Sphere;
-use crate::utility::random_f64;
-use crate::utility::random_f64_range;
-use crate::vec3::{Color, Point3, Vec3};
+use crate::scene::{CameraSettings, SceneSource};
+use crate::tile::Region;
+use crate::vec3::Color;
 use rayon::prelude::*;
-use std::io::{self, Write};
-use std::sync::Arc;
-
-use std::sync::mpsc::channel;
-use std::thread;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[macro_use]
 extern crate macro_attr;
 #[macro_use]
 extern crate newtype_derive;
 
+mod aabb;
+mod aarect;
+mod adaptive;
+mod bvh;
 mod camera;
+mod checkpoint;
+mod constant_medium;
+mod environment;
+mod grid_medium;
+mod hdr;
 mod hit;
+mod mat4;
 mod material;
+mod moving_sphere;
+mod obj;
+mod onb;
+mod options;
+mod output;
+mod pdf;
+mod perlin;
+mod random;
 mod ray;
+mod scene;
+mod scene_file;
 mod sphere;
+mod texture;
+mod tile;
+mod transform;
+mod triangle;
 mod utility;
 mod vec3;
 
-fn ray_color(r: &Ray, world: &dyn Hittable, depth: i32) -> Color {
-    // If we've exceeded the ray bounce limit, no more light is gathered.
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
+/// Estimate the light arriving at a diffuse hit directly from the lights, by
+/// sampling a point on one and tracing a shadow ray towards it. The estimate
+/// is weighted against finding the light by scattering with `scattering`.
+fn sample_lights(
+    r: &Ray,
+    rec: &HitRecord,
+    attenuation: Color,
+    scattering: &dyn Pdf,
+    lights: &HittableList,
+    world: &dyn Hittable,
+    rng: &mut Rng,
+) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    if lights.objects().is_empty() {
+        return black;
     }
 
-    if let Some(rec) = world.hit(r, 0.001, f64::INFINITY) {
-        if let Some((attenuation, scattered)) = rec.material.scatter(r, &rec) {
-            return attenuation * ray_color(&scattered, world, depth - 1);
-        } else {
-            return Color::new(0.0, 0.0, 0.0);
-        }
+    let light_pdf = HittablePdf::new(lights, rec.p);
+    let towards_light = match light_pdf.generate(rng) {
+        Some(direction) => Ray::new(rec.p, direction, r.time()),
+        None => return black,
+    };
+    let pdf = light_pdf.value(&towards_light.direction());
+    let scattering_pdf = rec.material.scattering_pdf(r, rec, &towards_light);
+    if pdf <= 0.0 || scattering_pdf <= 0.0 {
+        return black;
     }
 
-    let unit_direction = Vec3::unit_vector(r.direction());
-    let t = 0.5 * (unit_direction.y() + 1.0);
-    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+    // The shadow ray reaches the light unless something else is in the way,
+    // which may be a volume that the ray only scatters off at random.
+    let light = match lights.hit(&towards_light, 0.001, f64::INFINITY, rng) {
+        Some(light) => light,
+        None => return black,
+    };
+    if world
+        .hit(&towards_light, 0.001, light.t * (1.0 - 1e-6), rng)
+        .is_some()
+    {
+        return black;
+    }
+    let emitted = light.material.emitted(light.u, light.v, &light.p);
+
+    let weight = utility::power_heuristic(pdf, scattering.value(&towards_light.direction()));
+    (weight * scattering_pdf / pdf) * attenuation * emitted
 }
 
-fn random_scene() -> HittableList {
-    let mut world = HittableList::default();
-
-    let ground_material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
-    world.add(Sphere::new_arc(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        ground_material,
-    ));
-
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat = random_f64();
-            let center = Point3::new(
-                a as f64 + 0.9 * random_f64(),
-                0.2,
-                b as f64 + 0.9 * random_f64(),
-            );
+/// Returns true if the hit along the ray is on one of the lights.
+fn is_light_hit(r: &Ray, rec: &HitRecord, lights: &HittableList, rng: &mut Rng) -> bool {
+    match lights.hit(r, 0.001, f64::INFINITY, rng) {
+        Some(light) => (light.t - rec.t).abs() <= 1e-6 * rec.t,
+        None => false,
+    }
+}
+
+/// Estimate the light arriving at a diffuse hit directly from the
+/// environment, by sampling a direction towards it and checking that it isn't
+/// blocked. The estimate is weighted against finding the environment by
+/// scattering with `scattering`.
+fn sample_environment(
+    r: &Ray,
+    rec: &HitRecord,
+    attenuation: Color,
+    scattering: &dyn Pdf,
+    environment: &dyn Environment,
+    world: &dyn Hittable,
+    rng: &mut Rng,
+) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let (direction, light_pdf) = match environment.sample(rng) {
+        Some(sample) => sample,
+        None => return black,
+    };
+
+    let towards_light = Ray::new(rec.p, direction, r.time());
+    let scattering_pdf = rec.material.scattering_pdf(r, rec, &towards_light);
+    if scattering_pdf <= 0.0
+        || world
+            .hit(&towards_light, 0.001, f64::INFINITY, rng)
+            .is_some()
+    {
+        return black;
+    }
 
-            if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                let sphere_material: Arc<dyn Material + Send + Sync> = if choose_mat < 0.8 {
-                    // Diffuse
-                    let albedo = Color::random() * Color::random();
-                    Lambertian::new(albedo)
-                } else if choose_mat < 0.95 {
-                    // Metal
-                    let albedo = Color::random();
-                    let fuzz = random_f64_range(0.0, 0.5);
-                    Metal::new(albedo, fuzz)
-                } else {
-                    // Glass
-                    Dielectric::new(1.5)
+    let weight = utility::power_heuristic(light_pdf, scattering.value(&direction));
+    (weight * scattering_pdf / light_pdf) * attenuation * environment.value(&direction)
+}
+
+/// Returns the light arriving along a ray.
+///
+/// The path is followed for up to `max_depth` bounces. After `min_depth`
+/// bounces it is ended at random with a probability based on how much light
+/// it still carries, and the paths that continue are scaled up to make up
+/// for the ones that were ended.
+fn ray_color(
+    mut ray: Ray,
+    environment: &dyn Environment,
+    world: &dyn Hittable,
+    lights: &HittableList,
+    min_depth: u32,
+    max_depth: u32,
+    rng: &mut Rng,
+) -> Color {
+    let mut color = Color::new(0.0, 0.0, 0.0);
+    // The fraction of the light arriving along the current ray that reaches
+    // the camera.
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    // If the current ray was scattered diffusely, the density its direction
+    // was chosen with. The light it finds is weighted against sampling the
+    // lights and the environment directly.
+    let mut sampling_pdf = None;
+
+    for depth in 0..max_depth {
+        let rec = match world.hit(&ray, 0.001, f64::INFINITY, rng) {
+            Some(rec) => rec,
+            None => {
+                let direction = ray.direction();
+                let weight = match sampling_pdf {
+                    Some(pdf) => utility::power_heuristic(pdf, environment.pdf(&direction)),
+                    None => 1.0,
                 };
+                color += weight * throughput * environment.value(&direction);
+                break;
+            }
+        };
+
+        // Only emission from the lights could also have been found by
+        // sampling them, emitting volumes and the like are only found here.
+        let mut emitted = rec.material.emitted(rec.u, rec.v, &rec.p);
+        if let Some(pdf) = sampling_pdf {
+            if emitted.length_squared() > 0.0 && is_light_hit(&ray, &rec, lights, rng) {
+                let light_pdf = lights.pdf_value(&ray.origin(), &ray.direction());
+                emitted *= utility::power_heuristic(pdf, light_pdf);
+            }
+        }
+        color += throughput * emitted;
+
+        match rec.material.scatter(&ray, &rec, rng) {
+            Some(ScatterRecord::Specular {
+                attenuation,
+                ray: scattered,
+            }) => {
+                throughput = throughput * attenuation;
+                ray = scattered;
+                sampling_pdf = None;
+            }
+            Some(ScatterRecord::Diffuse {
+                attenuation,
+                pdf: scattering,
+            }) => {
+                let scattering = scattering.as_ref();
+                let direct = sample_lights(&ray, &rec, attenuation, scattering, lights, world, rng)
+                    + sample_environment(
+                        &ray,
+                        &rec,
+                        attenuation,
+                        scattering,
+                        environment,
+                        world,
+                        rng,
+                    );
+                color += throughput * direct;
+
+                let scattered = Ray::new(rec.p, scattering.generate(rng), ray.time());
+                let pdf = scattering.value(&scattered.direction());
+                let scattering_pdf = rec.material.scattering_pdf(&ray, &rec, &scattered);
+                if pdf <= 0.0 || scattering_pdf <= 0.0 {
+                    break;
+                }
+
+                throughput = (scattering_pdf / pdf) * throughput * attenuation;
+                ray = scattered;
+                sampling_pdf = Some(pdf);
+            }
+            None => break,
+        }
 
-                world.add(Sphere::new_arc(center, 0.2, sphere_material));
+        if depth + 1 >= min_depth {
+            let survival = f64::min(
+                f64::max(throughput.x(), f64::max(throughput.y(), throughput.z())),
+                0.95,
+            );
+            if rng.random_f64() >= survival {
+                break;
             }
+            throughput /= survival;
         }
     }
 
-    let material1 = Dielectric::new(1.5);
-    world.add(Sphere::new_arc(Point3::new(0.0, 1.0, 0.0), 1.0, material1));
+    color
+}
 
-    let material2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
-    world.add(Sphere::new_arc(Point3::new(-4.0, 1.0, 0.0), 1.0, material2));
+/// Everything needed to render samples of the image.
+struct Renderer<'a> {
+    camera: &'a Camera,
+    environment: &'a (dyn Environment + Send + Sync),
+    world: &'a (dyn Hittable + Send + Sync),
+    lights: &'a HittableList,
+    width: usize,
+    height: usize,
+    min_depth: u32,
+    max_depth: u32,
+    seed: u64,
+    tiles: &'a [Region],
+}
 
-    let material3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
-    world.add(Sphere::new_arc(Point3::new(4.0, 1.0, 0.0), 1.0, material3));
+impl Renderer<'_> {
+    /// Render the next pass over the tiles, taking each pixel from the
+    /// samples it already has up to its count in `pass_ends`. `on_tile` is
+    /// called as each tile is finished.
+    ///
+    /// Returns the sum and luminance statistics of the new samples of each
+    /// pixel row by row from the top left, or None if `stop` is set or the
+    /// deadline passes before the pass is finished.
+    fn render_pass(
+        &self,
+        sample_counts: &[u32],
+        pass_ends: &[u32],
+        stop: &AtomicBool,
+        deadline: Option<Instant>,
+        on_tile: &(dyn Fn(&Region) + Sync),
+    ) -> Option<Vec<(Color, RunningVariance)>> {
+        let framebuffer = Mutex::new(vec![
+            (Color::new(0.0, 0.0, 0.0), RunningVariance::default());
+            self.width * self.height
+        ]);
+        let next_tile = AtomicUsize::new(0);
+        let tiles_finished = AtomicUsize::new(0);
+
+        // Every thread takes the next tile until there are none left, so the
+        // tiles are started in order.
+        (0..rayon::current_num_threads())
+            .into_par_iter()
+            .for_each(|_| loop {
+                if stop.load(Ordering::Relaxed)
+                    || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                {
+                    return;
+                }
+                let tile = match self.tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                    Some(tile) => tile,
+                    None => return,
+                };
 
-    world
+                let pixels: Vec<_> = (tile.y0..tile.y1)
+                    .flat_map(|y| (tile.x0..tile.x1).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        let index = y * self.width + x;
+                        let samples = sample_counts[index]..pass_ends[index];
+                        self.render_pixel(x, self.height - 1 - y, samples)
+                    })
+                    .collect();
+
+                let mut framebuffer = framebuffer.lock().unwrap();
+                for (index, pixel) in tile.pixel_indices(self.width).zip(pixels) {
+                    framebuffer[index] = pixel;
+                }
+                drop(framebuffer);
+
+                tiles_finished.fetch_add(1, Ordering::Relaxed);
+                on_tile(tile);
+            });
+
+        if tiles_finished.into_inner() == self.tiles.len() {
+            Some(framebuffer.into_inner().unwrap())
+        } else {
+            None
+        }
+    }
+
+    /// The sum of the given samples of the pixel in column `i` and row `j`,
+    /// counting rows up from the bottom, and the statistics of their
+    /// luminance.
+    fn render_pixel(&self, i: usize, j: usize, samples: Range<u32>) -> (Color, RunningVariance) {
+        let mut pixel_color = Color::new(0.0, 0.0, 0.0);
+        let mut luminance = RunningVariance::default();
+
+        for s in samples {
+            let mut rng = Rng::for_sample(self.seed, i as u32, j as u32, s);
+            let u = (i as f64 + rng.random_f64()) / (self.width - 1) as f64;
+            let v = (j as f64 + rng.random_f64()) / (self.height - 1) as f64;
+            let r = self.camera.get_ray(u, v, &mut rng);
+            let sample = ray_color(
+                r,
+                self.environment,
+                self.world,
+                self.lights,
+                self.min_depth,
+                self.max_depth,
+                &mut rng,
+            );
+            pixel_color += sample;
+            luminance.add(sample.luminance());
+        }
+
+        (pixel_color, luminance)
+    }
 }
 
-fn main() {
-    let aspect_ratio = 16.0 / 9.0;
-    let image_width: i32 = 1200;
-    let image_height: i32 = (image_width as f64 / aspect_ratio) as i32;
-    let samples_per_pixel = 100;
-    let max_depth = 50;
-
-    print!("P3\n{} {} \n255\n", image_width, image_height);
-
-    let world = random_scene();
-
-    let lookfrom = Point3::new(13.0, 2.0, 3.0);
-    let lookat = Point3::new(0.0, 0.0, 0.0);
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-
-    let camera = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        20.0,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
+/// The number of samples a pixel with `done` samples has after the next pass.
+/// With an adaptive threshold, pixels stop early once their relative error is
+/// under it.
+fn pass_end(done: u32, luminance: &RunningVariance, options: &Options) -> u32 {
+    if let Some(threshold) = options.adaptive_threshold {
+        if done >= options.adaptive_min_samples && luminance.relative_error() < threshold {
+            return done;
+        }
+    }
+    u32::max(
+        done,
+        u32::min(done.saturating_add(options.pass_samples), options.samples),
+    )
+}
+
+/// The samples taken so far of every pixel, row by row from the top left.
+struct Accumulation {
+    sums: Vec<Color>,
+    sample_counts: Vec<u32>,
+    luminance: Vec<RunningVariance>,
+}
+
+impl Accumulation {
+    fn new(pixel_count: usize) -> Self {
+        Accumulation {
+            sums: vec![Color::new(0.0, 0.0, 0.0); pixel_count],
+            sample_counts: vec![0; pixel_count],
+            luminance: vec![RunningVariance::default(); pixel_count],
+        }
+    }
+
+    /// The sample count every pixel should reach in the next pass, and the
+    /// pixels of the region that still need more samples.
+    fn next_pass(
+        &self,
+        region: Region,
+        image_width: usize,
+        options: &Options,
+    ) -> (Vec<u32>, Vec<usize>) {
+        let pass_ends: Vec<u32> = self
+            .sample_counts
+            .iter()
+            .zip(self.luminance.iter())
+            .map(|(&done, luminance)| pass_end(done, luminance, options))
+            .collect();
+        let pixels_left = region
+            .pixel_indices(image_width)
+            .filter(|&index| pass_ends[index] > self.sample_counts[index])
+            .collect();
+        (pass_ends, pixels_left)
+    }
+
+    /// Add a finished pass to the given pixels.
+    fn add_pass(&mut self, pass: &[(Color, RunningVariance)], pass_ends: &[u32], pixels: &[usize]) {
+        for &index in pixels {
+            let (sum, pass_luminance) = &pass[index];
+            self.sums[index] += *sum;
+            self.luminance[index].merge(pass_luminance);
+            self.sample_counts[index] = pass_ends[index];
+        }
+    }
+}
+
+/// The image averaged from the sums of the samples of each pixel.
+fn average_image(width: usize, height: usize, sums: &[Color], sample_counts: &[u32]) -> Image {
+    let pixels = sums
+        .iter()
+        .zip(sample_counts.iter())
+        .map(|(&sum, &count)| (1.0 / u32::max(count, 1) as f64) * sum)
+        .collect();
+    Image::new(width, height, pixels)
+}
+
+/// Write a file through a temporary file that is moved into place once it is
+/// finished, so the file is never seen half written.
+fn write_file_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&temp_path, path)
+}
+
+/// A hash of everything that changes the samples rendered, to check that a
+/// checkpoint is resumed with the same scene and settings. Only the scene
+/// file itself is hashed, not the meshes or images it loads.
+fn render_settings_hash(options: &Options, camera_settings: &CameraSettings) -> io::Result<u64> {
+    let scene_file = match &options.scene {
+        SceneSource::File(path) => fs::read(path)?,
+        SceneSource::Builtin(_) => Vec::new(),
+    };
+    // The pass size is included as the samples are summed pass by pass, and
+    // the adaptive settings as they choose how many samples each pixel takes.
+    // The region keeps a render from being resumed with pixels from another.
+    let settings = format!(
+        "{:?} {}x{} {:?} {} {:?} depth {} to {} seed {} pass {} adaptive {:?} from {}",
+        options.scene,
+        options.width,
+        options.image_height(),
+        options.render_region(),
+        options.image_aspect_ratio(),
+        camera_settings,
+        options.min_depth,
+        options.max_depth,
+        options.seed,
+        options.pass_samples,
+        options.adaptive_threshold,
+        options.adaptive_min_samples
     );
+    Ok(checkpoint::settings_hash(&[
+        settings.as_bytes(),
+        &scene_file,
+    ]))
+}
 
-    let (send, recv) = channel::<i32>();
+fn main() {
+    let options = Options::from_args_validated();
 
-    thread::spawn(move || {
-        let mut scanlines_remaining = image_height;
+    if let Some(threads) = options.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()
+            .unwrap();
+    }
 
-        while scanlines_remaining > 0 {
-            recv.recv().unwrap();
-            scanlines_remaining -= 1;
+    let image_width = options.width as usize;
+    let image_height = options.image_height() as usize;
+    let samples_per_pixel = options.samples;
+
+    let scene = match options.scene.load(options.seed) {
+        Ok(scene) => scene,
+        Err(e) => exit_with_error(&format!("unable to load scene: {}", e)),
+    };
+    let camera_settings = options.camera_settings(scene.camera);
+    if let Err(message) = camera_settings.validate() {
+        exit_with_error(&message);
+    }
 
-            eprint!("\rRender Scanlines remaining: {:4}", scanlines_remaining);
-            io::stderr().flush().unwrap();
+    let settings_hash = match render_settings_hash(&options, &camera_settings) {
+        Ok(hash) => hash,
+        Err(e) => exit_with_error(&format!("unable to read scene: {}", e)),
+    };
+    let mut accumulation = match (&options.checkpoint, options.resume) {
+        (Some(path), true) => {
+            let checkpoint = match File::open(path)
+                .and_then(|file| Checkpoint::read(&mut BufReader::new(file)))
+            {
+                Ok(checkpoint) => checkpoint,
+                Err(e) => exit_with_error(&format!(
+                    "unable to read checkpoint {}: {}",
+                    path.display(),
+                    e
+                )),
+            };
+            if checkpoint.settings_hash != settings_hash
+                || (checkpoint.width, checkpoint.height) != (image_width, image_height)
+            {
+                exit_with_error(&format!(
+                    "checkpoint {} was saved with a different scene or settings",
+                    path.display()
+                ));
+            }
+            Accumulation {
+                sums: checkpoint.sums,
+                sample_counts: checkpoint.sample_counts,
+                luminance: checkpoint.luminance,
+            }
+        }
+        _ => Accumulation::new(image_width * image_height),
+    };
+    let save_checkpoint = |accumulation: &Accumulation| {
+        if let Some(path) = &options.checkpoint {
+            let checkpoint = Checkpoint {
+                settings_hash,
+                width: image_width,
+                height: image_height,
+                sums: accumulation.sums.clone(),
+                sample_counts: accumulation.sample_counts.clone(),
+                luminance: accumulation.luminance.clone(),
+            };
+            if let Err(e) = write_file_atomically(path, |writer| checkpoint.write(writer)) {
+                eprintln!("Unable to write checkpoint {}: {}", path.display(), e);
+            }
+        }
+    };
+
+    let world = BvhNode::new(scene.world);
+    let camera = camera_settings.build(options.image_aspect_ratio());
+    let region = options.render_region();
+    let tiles = tile::tiles(region, options.tile_size as usize, options.tile_order);
+    let renderer = Renderer {
+        camera: &camera,
+        environment: scene.environment.as_ref(),
+        world: &world,
+        lights: &scene.lights,
+        width: image_width,
+        height: image_height,
+        min_depth: options.min_depth,
+        max_depth: options.max_depth,
+        seed: options.seed,
+        tiles: &tiles,
+    };
+
+    // The first Ctrl-C stops the render after saving the passes completed so
+    // far, a second one exits straight away.
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        if handler_stop.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
         }
-    });
+        eprint!("\nStopping, press Ctrl-C again to exit without saving");
+    }) {
+        eprintln!("Unable to handle Ctrl-C: {}", e);
+    }
+
+    let start = Instant::now();
+    let mut last_snapshot = start;
+    let mut last_checkpoint = start;
+    let deadline = options
+        .time_limit
+        .map(|limit| start + Duration::from_secs_f64(limit));
+    let (mut pass_ends, mut pixels_left) = accumulation.next_pass(region, image_width, &options);
+
+    while !pixels_left.is_empty() {
+        let samples_done = pixels_left
+            .iter()
+            .map(|&index| accumulation.sample_counts[index])
+            .min()
+            .unwrap_or(0);
+        eprint!(
+            "Samples {} to {} of {}",
+            samples_done + 1,
+            u32::min(samples_done + options.pass_samples, samples_per_pixel),
+            samples_per_pixel
+        );
+        if options.adaptive_threshold.is_some() {
+            eprint!(", {} pixels left", pixels_left.len());
+        }
+        eprintln!();
 
-    let image: Vec<Vec<_>> = (0..image_height)
-        .into_par_iter()
-        .rev()
-        .map_with(send, |s, j| {
-            let scanline: Vec<_> = (0..image_width)
-                .map(|i| {
-                    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
+        let tiles_remaining = AtomicUsize::new(tiles.len());
+        let on_tile = |_: &Region| {
+            let remaining = tiles_remaining.fetch_sub(1, Ordering::Relaxed) - 1;
+            eprint!("\rRender Tiles remaining: {:4}", remaining);
+            io::stderr().flush().unwrap();
+        };
+        // A pass cut short by the time limit is dropped, like one stopped by
+        // Ctrl-C, leaving the passes finished before it. The first pass is
+        // always finished so that there is an image to write.
+        let started = accumulation.sample_counts.iter().any(|&count| count > 0);
+        let pass = renderer.render_pass(
+            &accumulation.sample_counts,
+            &pass_ends,
+            &stop,
+            deadline.filter(|_| started),
+            &on_tile,
+        );
+        if let Some(pass) = &pass {
+            accumulation.add_pass(pass, &pass_ends, &pixels_left);
+        }
+        eprintln!();
 
-                    (0..samples_per_pixel).for_each(|_| {
-                        let u = (i as f64 + random_f64()) / (image_width - 1) as f64;
-                        let v = (j as f64 + random_f64()) / (image_height - 1) as f64;
-                        let r = camera.get_ray(u, v);
-                        pixel_color += ray_color(&r, &world, max_depth);
-                    });
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            eprintln!(
+                "Reached the time limit after {:.1} seconds",
+                start.elapsed().as_secs_f64()
+            );
+            break;
+        }
+        if pass.is_none() {
+            break;
+        }
+        (pass_ends, pixels_left) = accumulation.next_pass(region, image_width, &options);
+        if stop.load(Ordering::Relaxed) || pixels_left.is_empty() {
+            break;
+        }
 
-                    pixel_color
-                })
-                .collect();
+        if let Some(path) = &options.snapshot {
+            if last_snapshot.elapsed().as_secs_f64() >= options.snapshot_interval {
+                let image = average_image(
+                    image_width,
+                    image_height,
+                    &accumulation.sums,
+                    &accumulation.sample_counts,
+                );
+                if let Err(e) = write_file_atomically(path, |writer| {
+                    let format = ImageFormat::from_path(path).unwrap_or(ImageFormat::Png);
+                    write_image(&image, format, writer)
+                }) {
+                    eprintln!("Unable to write snapshot {}: {}", path.display(), e);
+                }
+                last_snapshot = Instant::now();
+            }
+        }
+        if last_checkpoint.elapsed().as_secs_f64() >= options.checkpoint_interval {
+            save_checkpoint(&accumulation);
+            last_checkpoint = Instant::now();
+        }
+    }
 
-            s.send(1).unwrap();
+    let sample_counts = &accumulation.sample_counts;
+    if sample_counts.iter().all(|&count| count == 0) {
+        eprintln!("\nStopped before the first pass was finished, no image written");
+        std::process::exit(1);
+    }
+    save_checkpoint(&accumulation);
+
+    if let Some(path) = &options.heatmap {
+        let image = adaptive::heatmap(image_width, image_height, sample_counts, samples_per_pixel);
+        if let Err(e) = write_file_atomically(path, |writer| {
+            let format = ImageFormat::from_path(path).unwrap_or(ImageFormat::Png);
+            write_image(&image, format, writer)
+        }) {
+            eprintln!("Unable to write heatmap {}: {}", path.display(), e);
+        }
+    }
 
-            scanline
-        })
-        .collect();
+    let region_samples: u64 = region
+        .pixel_indices(image_width)
+        .map(|index| sample_counts[index] as u64)
+        .sum();
+    eprint!(
+        "\nPrinting {:.1} samples per pixel on average...",
+        region_samples as f64 / (region.width() * region.height()) as f64
+    );
 
-    eprint!("\nPrinting...");
+    let image = average_image(image_width, image_height, &accumulation.sums, sample_counts);
 
-    image.iter().for_each(|v| {
-        v.iter().for_each(|color| {
-            print!("{}", color.get_color_string(samples_per_pixel));
-        })
-    });
+    // The output is only opened now, so a render that fails or is stopped
+    // early leaves any existing file alone.
+    let format = options.output_format();
+    let written = match &options.output {
+        Some(path) => write_file_atomically(path, |writer| write_image(&image, format, writer)),
+        None => {
+            let mut output = BufWriter::new(io::stdout());
+            write_image(&image, format, &mut output).and_then(|_| output.flush())
+        }
+    };
+    if let Err(e) = written {
+        eprintln!("\nUnable to write image: {}", e);
+        std::process::exit(1);
+    }
 
     eprint!("\nDone.\n");
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::adaptive::RunningVariance;
+    use crate::camera::Camera;
+    use crate::environment::SolidBackground;
+    use crate::hit::HittableList;
+    use crate::options::Options;
+    use crate::scene::CameraSettings;
+    use crate::tile::{self, Region, TileOrder};
+    use crate::vec3::{Color, Point3, Vec3};
+    use crate::{average_image, pass_end, Accumulation, Renderer};
+    use std::sync::atomic::AtomicBool;
+    use std::time::Instant;
+    use structopt::StructOpt;
+
+    fn options(args: &[&str]) -> Options {
+        Options::from_iter(["raytracing"].iter().chain(args.iter()))
+    }
+
+    fn camera() -> Camera {
+        CameraSettings {
+            lookfrom: Point3::new(0.0, 0.0, 0.0),
+            lookat: Point3::new(0.0, 0.0, -1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 40.0,
+            aperture: 0.0,
+            focus_dist: 1.0,
+            time0: 0.0,
+            time1: 0.0,
+        }
+        .build(1.0)
+    }
+
+    #[test]
+    fn passes_end_at_the_target_or_when_converged() {
+        let fixed = options(&["--samples", "40", "--pass-samples", "16"]);
+        let none = RunningVariance::default();
+        assert_eq!(pass_end(0, &none, &fixed), 16);
+        assert_eq!(pass_end(32, &none, &fixed), 40);
+        assert_eq!(pass_end(40, &none, &fixed), 40);
+        assert_eq!(pass_end(50, &none, &fixed), 50);
+
+        let adaptive = options(&[
+            "--samples",
+            "40",
+            "--pass-samples",
+            "4",
+            "--adaptive-threshold",
+            "0.1",
+            "--adaptive-min-samples",
+            "8",
+        ]);
+        let mut flat = RunningVariance::default();
+        (0..4).for_each(|_| flat.add(0.5));
+        assert_eq!(pass_end(4, &flat, &adaptive), 8);
+        (0..4).for_each(|_| flat.add(0.5));
+        assert_eq!(pass_end(8, &flat, &adaptive), 8);
+
+        let mut noisy = RunningVariance::default();
+        (0..8).for_each(|i| noisy.add((i % 2) as f64));
+        assert_eq!(pass_end(8, &noisy, &adaptive), 12);
+    }
+
+    #[test]
+    fn average_of_the_samples() {
+        // Pixels without samples are black rather than NaN.
+        let sums = [Color::new(4.0, 2.0, 0.0), Color::new(0.0, 0.0, 0.0)];
+        let image = average_image(2, 1, &sums, &[4, 0]);
+        assert_eq!(*image.pixels()[0], *Color::new(1.0, 0.5, 0.0));
+        assert_eq!(*image.pixels()[1], *Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn passes_render_the_region_up_to_the_target() {
+        let background = Color::new(0.5, 0.25, 1.0);
+        let environment = SolidBackground::new(background);
+        let world = HittableList::default();
+        let camera = camera();
+        let region = Region::new(2, 1, 6, 5);
+        let tiles = tile::tiles(region, 3, TileOrder::Spiral);
+        let renderer = Renderer {
+            camera: &camera,
+            environment: environment.as_ref(),
+            world: &world,
+            lights: &world,
+            width: 8,
+            height: 6,
+            min_depth: 5,
+            max_depth: 10,
+            seed: 0,
+            tiles: &tiles,
+        };
+        let options = options(&["--samples", "40", "--pass-samples", "16"]);
+        let stop = AtomicBool::new(false);
+        let mut accumulation = Accumulation::new(8 * 6);
+
+        // A pass that is stopped or out of time gives nothing.
+        let (pass_ends, _) = accumulation.next_pass(region, 8, &options);
+        let counts = &accumulation.sample_counts;
+        let past = Some(Instant::now());
+        assert!(renderer
+            .render_pass(counts, &pass_ends, &stop, past, &|_| {})
+            .is_none());
+        let stopped = AtomicBool::new(true);
+        assert!(renderer
+            .render_pass(counts, &pass_ends, &stopped, None, &|_| {})
+            .is_none());
+
+        let mut passes = 0;
+        loop {
+            let (pass_ends, pixels_left) = accumulation.next_pass(region, 8, &options);
+            if pixels_left.is_empty() {
+                break;
+            }
+            let pass = renderer
+                .render_pass(
+                    &accumulation.sample_counts,
+                    &pass_ends,
+                    &stop,
+                    None,
+                    &|_| {},
+                )
+                .unwrap();
+            accumulation.add_pass(&pass, &pass_ends, &pixels_left);
+            passes += 1;
+        }
+        assert_eq!(passes, 3);
+
+        let image = average_image(8, 6, &accumulation.sums, &accumulation.sample_counts);
+        for index in 0..8 * 6 {
+            let pixel = Region::new(index % 8, index / 8, index % 8 + 1, index / 8 + 1);
+            if region.contains(&pixel) {
+                assert_eq!(accumulation.sample_counts[index], 40);
+                assert_eq!(accumulation.luminance[index].count, 40);
+                assert!((*image.pixels()[index] - *background).length() < 1e-12);
+            } else {
+                assert_eq!(accumulation.sample_counts[index], 0);
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use crate::vec3::{Point3, Vec3};
+use std::ops::Mul;
+
+/// A 4x4 matrix for affine transforms of points and vectors, stored by rows.
+/// The last row is always (0, 0, 0, 1).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4([[f64; 4]; 4]);
+
+impl Mat4 {
+    pub fn identity() -> Self {
+        Mat4([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Mat4([
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// A rotation counterclockwise about an axis through the origin, when
+    /// looking down the axis towards the origin.
+    pub fn rotation(axis: Vec3, degrees: f64) -> Self {
+        let a = Vec3::unit_vector(axis);
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        Mat4([
+            [
+                t * x * x + cos,
+                t * x * y - sin * z,
+                t * x * z + sin * y,
+                0.0,
+            ],
+            [
+                t * x * y + sin * z,
+                t * y * y + cos,
+                t * y * z - sin * x,
+                0.0,
+            ],
+            [
+                t * x * z - sin * y,
+                t * y * z + sin * x,
+                t * z * z + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// A scale by a separate factor along each axis.
+    pub fn scaling(factors: Vec3) -> Self {
+        Mat4([
+            [factors.x(), 0.0, 0.0, 0.0],
+            [0.0, factors.y(), 0.0, 0.0],
+            [0.0, 0.0, factors.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// The determinant of the upper 3x3 part, how much the transform scales
+    /// volumes.
+    pub fn determinant(&self) -> f64 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// The inverse transform, or None if the transform flattens space and
+    /// can't be undone.
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() < 1e-12 || !det.is_finite() {
+            return None;
+        }
+
+        // The inverse of the 3x3 part is its adjugate over the determinant,
+        // and the translation is undone after it.
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) / det
+        };
+        let mut inverse = Mat4([
+            [
+                cofactor(1, 2, 1, 2),
+                -cofactor(0, 2, 1, 2),
+                cofactor(0, 1, 1, 2),
+                0.0,
+            ],
+            [
+                -cofactor(1, 2, 0, 2),
+                cofactor(0, 2, 0, 2),
+                -cofactor(0, 1, 0, 2),
+                0.0,
+            ],
+            [
+                cofactor(1, 2, 0, 1),
+                -cofactor(0, 2, 0, 1),
+                cofactor(0, 1, 0, 1),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+
+        let translation = inverse.transform_vector(&Vec3::new(m[0][3], m[1][3], m[2][3]));
+        for row in 0..3 {
+            inverse.0[row][3] = -translation[row];
+        }
+        Some(inverse)
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut transposed = Mat4([[0.0; 4]; 4]);
+        for row in 0..4 {
+            for col in 0..4 {
+                transposed.0[row][col] = self.0[col][row];
+            }
+        }
+        transposed
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        self.transform_vector(p) + Vec3::new(self.0[0][3], self.0[1][3], self.0[2][3])
+    }
+
+    /// Transform a direction, which is not affected by translation.
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+/// Composes transforms, `a * b` applies `b` first and then `a`.
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Self::Output {
+        let mut product = Mat4([[0.0; 4]; 4]);
+        for row in 0..4 {
+            for col in 0..4 {
+                product.0[row][col] = (0..4).map(|i| self.0[row][i] * rhs.0[i][col]).sum();
+            }
+        }
+        product
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mat4::Mat4;
+    use crate::vec3::{Point3, Vec3};
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn transforms_and_inverses() {
+        let rotation = Mat4::rotation(Vec3::new(0.0, 1.0, 0.0), 90.0);
+        assert_near(
+            rotation.transform_vector(&Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+
+        let m = Mat4::translation(Vec3::new(1.0, 2.0, 3.0))
+            * Mat4::rotation(Vec3::new(1.0, 1.0, 0.0), 30.0)
+            * Mat4::scaling(Vec3::new(2.0, 0.5, -1.0));
+        let p = Point3::new(0.3, -0.7, 1.1);
+        let inverse = m.inverse().unwrap();
+        assert_near(inverse.transform_point(&m.transform_point(&p)), p);
+        assert_near(m.transform_point(&inverse.transform_point(&p)), p);
+        assert!((m.determinant() + 1.0).abs() < 1e-9);
+
+        assert!(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+}
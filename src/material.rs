@@ -1,52 +1,100 @@
 use crate::hit::HitRecord;
+use crate::pdf::{CosinePdf, Pdf, SpherePdf};
+use crate::random::Rng;
 use crate::ray::Ray;
-use crate::utility::random_f64;
+use crate::texture::{SolidColor, Texture};
+use crate::utility;
 use crate::vec3::Color;
-use crate::vec3::Vec3;
+use crate::vec3::{Point3, Vec3};
 use std::sync::Arc;
 
+/// How a material scatters an incoming ray.
+#[derive(Debug)]
+pub enum ScatterRecord {
+    /// Scattered along a single ray, like a mirror or glass.
+    Specular { attenuation: Color, ray: Ray },
+    /// Scattered in directions distributed by `pdf`, which the integrator
+    /// may mix with other pdfs, like sampling towards lights.
+    Diffuse {
+        attenuation: Color,
+        pdf: Box<dyn Pdf>,
+    },
+}
+
 pub trait Material {
-    /// Returns the attenuation and scatter ray by the material in the
-    /// form of Option<(Color, Ray)> if the material did not absorb the ray.
+    /// Returns how the material scatters the ray, or None if the material
+    /// absorbs the ray.
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<ScatterRecord>;
+
+    /// Returns the light emitted by the material at the surface coordinates
+    /// u, v and the point p. Materials that are not light sources emit black.
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// Returns the probability density, over solid angle, of the material
+    /// scattering in the direction of `scattered`. Scattering towards a
+    /// direction is the attenuation from `scatter` times this density.
     ///
-    /// A material that absorbs the ray returns None.
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+    /// Only used for diffuse scattering, materials that always scatter
+    /// specularly don't need to implement it.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
 }
 
 impl std::fmt::Debug for dyn Material {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "dyn Material")
     }
 }
 
 #[derive(Debug)]
 pub struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture + Send + Sync>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Arc<Self> {
+        Lambertian::with_texture(SolidColor::new(albedo))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture + Send + Sync>) -> Arc<Self> {
         Arc::new(Lambertian { albedo })
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let scatter_direction = rec.normal + Vec3::random_unit_vector();
-        let scattered = Ray::new(rec.p, scatter_direction);
-        let attenuation = self.albedo;
-        Some((attenuation, scattered))
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, _rng: &mut Rng) -> Option<ScatterRecord> {
+        Some(ScatterRecord::Diffuse {
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: Box::new(CosinePdf::new(&rec.normal)),
+        })
+    }
+
+    /// Lambertian scattering is cosine weighted.
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = Vec3::dot(&rec.normal, &Vec3::unit_vector(scattered.direction()));
+        if cosine < 0.0 {
+            0.0
+        } else {
+            cosine / utility::PI
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct Metal {
-    albedo: Color,
+    albedo: Arc<dyn Texture + Send + Sync>,
     fuzz: f64,
 }
 
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Arc<Self> {
+        Metal::with_texture(SolidColor::new(albedo), fuzz)
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture + Send + Sync>, fuzz: f64) -> Arc<Self> {
         Arc::new(Metal {
             albedo,
             fuzz: if fuzz < 1.0 { fuzz } else { 1.0 },
@@ -55,12 +103,19 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<ScatterRecord> {
         let reflected = Vec3::reflect(&Vec3::unit_vector(r_in.direction()), &rec.normal);
-        let scattered = Ray::new(rec.p, reflected + self.fuzz * Vec3::random_in_unit_sphere());
-        let attenuation = self.albedo;
+        let scattered = Ray::new(
+            rec.p,
+            reflected + self.fuzz * Vec3::random_in_unit_sphere(rng),
+            r_in.time(),
+        );
+        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
         if Vec3::dot(&scattered.direction(), &rec.normal) > 0.0 {
-            Some((attenuation, scattered))
+            Some(ScatterRecord::Specular {
+                attenuation,
+                ray: scattered,
+            })
         } else {
             None
         }
@@ -85,7 +140,7 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<ScatterRecord> {
         let attenuation = Color::new(1.0, 1.0, 1.0);
         let etai_over_etat = if rec.front_face {
             1.0 / self.ref_idx
@@ -99,19 +154,86 @@ impl Material for Dielectric {
 
         if etai_over_etat * sin_theta > 1.0 {
             let reflected = Vec3::reflect(&unit_direction, &rec.normal);
-            let scattered = Ray::new(rec.p, reflected);
-            return Some((attenuation, scattered));
+            let scattered = Ray::new(rec.p, reflected, r_in.time());
+            return Some(ScatterRecord::Specular {
+                attenuation,
+                ray: scattered,
+            });
         }
 
         let reflect_prob = Dielectric::schlick(cos_theta, etai_over_etat);
-        if random_f64() < reflect_prob {
+        if rng.random_f64() < reflect_prob {
             let reflected = Vec3::reflect(&unit_direction, &rec.normal);
-            let scattered = Ray::new(rec.p, reflected);
-            return Some((attenuation, scattered));
+            let scattered = Ray::new(rec.p, reflected, r_in.time());
+            return Some(ScatterRecord::Specular {
+                attenuation,
+                ray: scattered,
+            });
         }
 
         let refracted = Vec3::refract(&unit_direction, &rec.normal, etai_over_etat);
-        let scattered = Ray::new(rec.p, refracted);
-        Some((attenuation, scattered))
+        let scattered = Ray::new(rec.p, refracted, r_in.time());
+        Some(ScatterRecord::Specular {
+            attenuation,
+            ray: scattered,
+        })
+    }
+}
+
+/// A light source emitting the color of its texture evenly in all directions.
+/// It does not scatter any incoming light.
+#[derive(Debug)]
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture + Send + Sync>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Arc<Self> {
+        DiffuseLight::with_texture(SolidColor::new(emit))
+    }
+
+    pub fn with_texture(emit: Arc<dyn Texture + Send + Sync>) -> Arc<Self> {
+        Arc::new(DiffuseLight { emit })
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _rng: &mut Rng) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.emit.value(u, v, p)
+    }
+}
+
+/// The phase function of a participating medium that scatters light equally in
+/// all directions, tinted by its texture.
+#[derive(Debug)]
+pub struct Isotropic {
+    albedo: Arc<dyn Texture + Send + Sync>,
+}
+
+impl Isotropic {
+    #[allow(dead_code)]
+    pub fn new(albedo: Color) -> Arc<Self> {
+        Isotropic::with_texture(SolidColor::new(albedo))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture + Send + Sync>) -> Arc<Self> {
+        Arc::new(Isotropic { albedo })
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, _rng: &mut Rng) -> Option<ScatterRecord> {
+        Some(ScatterRecord::Diffuse {
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: Box::new(SpherePdf),
+        })
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * utility::PI)
     }
 }
@@ -0,0 +1,179 @@
+use crate::aabb::Aabb;
+use crate::hit::HitRecord;
+use crate::hit::Hittable;
+use crate::material::Material;
+use crate::random::Rng;
+use crate::ray::Ray;
+use crate::sphere::Sphere;
+use crate::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// A sphere moving in a straight line, from center0 at time0 to center1 at
+/// time1. It rests at center0 before time0 and at center1 after time1.
+pub struct MovingSphere {
+    center0: Point3,
+    center1: Point3,
+    time0: f64,
+    time1: f64,
+    radius: f64,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl MovingSphere {
+    pub fn new(
+        center0: Point3,
+        center1: Point3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            material,
+        }
+    }
+
+    pub fn new_arc(
+        center0: Point3,
+        center1: Point3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Arc<Self> {
+        Arc::new(MovingSphere::new(
+            center0, center1, time0, time1, radius, material,
+        ))
+    }
+
+    /// The center of the sphere at a point in time, interpolated between
+    /// center0 and center1. Times outside time0 to time1 are clamped to it, so
+    /// the sphere is always inside its bounding box.
+    pub fn center(&self, time: f64) -> Point3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+
+        let fraction = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + fraction * (self.center1 - self.center0)
+    }
+
+    fn hit_record(&self, r: &Ray, t: f64, center: Point3) -> HitRecord {
+        let p = r.at(t);
+        let outward_normal = (p - center) / self.radius;
+        let (u, v) = Sphere::get_sphere_uv(&((p - center) / self.radius.abs()));
+        HitRecord::new(p, r, outward_normal, t, u, v, self.material.clone())
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut Rng) -> Option<HitRecord> {
+        let center = self.center(r.time());
+        let oc = r.origin() - center;
+        let a = r.direction().length_squared();
+        let half_b = Vec3::dot(&oc, &r.direction());
+        let c = oc.length_squared() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+
+        if discriminant > 0.0 {
+            let root = discriminant.sqrt();
+            let temp = (-half_b - root) / a;
+            if temp < t_max && temp > t_min {
+                return Some(self.hit_record(r, temp, center));
+            }
+
+            let temp = (-half_b + root) / a;
+            if temp < t_max && temp > t_min {
+                return Some(self.hit_record(r, temp, center));
+            }
+        }
+
+        None
+    }
+
+    /// The box around the sphere over the whole of its motion from time0 to
+    /// time1.
+    fn aabb(&self) -> Option<Aabb> {
+        let radius = Vec3::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
+        let box0 = Aabb::new(self.center0 - radius, self.center0 + radius);
+        let box1 = Aabb::new(self.center1 - radius, self.center1 + radius);
+        Some(Aabb::surrounding_box(&box0, &box1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bvh::BvhNode;
+    use crate::hit::{Hittable, HittableList};
+    use crate::material::Lambertian;
+    use crate::moving_sphere::MovingSphere;
+    use crate::random::Rng;
+    use crate::ray::Ray;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    #[test]
+    fn hit_depends_on_time() {
+        let mut rng = Rng::new(0);
+        let sphere = MovingSphere::new(
+            Point3::new(0.0, 0.0, -2.0),
+            Point3::new(0.0, 2.0, -2.0),
+            0.0,
+            1.0,
+            0.5,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        );
+
+        let ray = |time| Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, -1.0), time);
+        assert!(sphere
+            .hit(&ray(0.0), 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+        let rec = sphere
+            .hit(&ray(0.5), 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert_eq!(rec.t, 1.5);
+        assert!(sphere
+            .hit(&ray(1.0), 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+
+        let bbox = sphere.aabb().unwrap();
+        assert_eq!(bbox.min(), Point3::new(-0.5, -0.5, -2.5));
+        assert_eq!(bbox.max(), Point3::new(0.5, 2.5, -1.5));
+    }
+
+    #[test]
+    fn rests_outside_its_time_range() {
+        let mut rng = Rng::new(0);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let mut list = HittableList::default();
+        list.add(MovingSphere::new_arc(
+            Point3::new(0.0, 0.0, -2.0),
+            Point3::new(0.0, 2.0, -2.0),
+            0.0,
+            1.0,
+            0.5,
+            material.clone(),
+        ));
+        list.add(Sphere::new_arc(Point3::new(10.0, 0.0, -2.0), 0.5, material));
+        let bvh = BvhNode::new(list);
+
+        // Before time0 the sphere is at center0, and after time1 at center1.
+        let ray = |y, time| Ray::new(Point3::new(0.0, y, 0.0), Vec3::new(0.0, 0.0, -1.0), time);
+        let rec = bvh
+            .hit(&ray(0.0, -1.0), 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert_eq!(rec.t, 1.5);
+        let rec = bvh
+            .hit(&ray(2.0, 3.0), 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert_eq!(rec.t, 1.5);
+        assert!(bvh
+            .hit(&ray(4.0, 3.0), 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+    }
+}
@@ -0,0 +1,516 @@
+//! Loading of Wavefront OBJ meshes and their MTL material libraries.
+//!
+//! Faces are triangulated as fans, and every group (`g` or `o`) and material
+//! combination becomes its own triangle mesh. MTL materials are mapped onto the
+//! materials the renderer supports:
+//!
+//! * Transparent materials (`d` < 1, `Tr` > 0, or a refraction `illum` model)
+//!   become `Dielectric` with the `Ni` index of refraction.
+//! * Materials whose specular color `Ks` is brighter than their diffuse color
+//!   `Kd` become `Metal`, with the fuzz derived from the `Ns` exponent.
+//! * Everything else becomes `Lambertian` with the `Kd` albedo.
+
+use crate::material::Material;
+use crate::material::{Dielectric, Lambertian, Metal};
+use crate::triangle::{MeshData, TriangleMesh};
+use crate::vec3::{Color, Point3, Vec3};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// An error encountered while loading an OBJ or MTL file.
+#[derive(Debug)]
+pub struct ObjError {
+    file: String,
+    /// The line the error occurred on, if known.
+    line: Option<usize>,
+    message: String,
+}
+
+impl ObjError {
+    fn new(file: &str, line: Option<usize>, message: String) -> Self {
+        ObjError {
+            file: file.to_string(),
+            line,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// A named group of faces sharing a material.
+pub struct ObjGroup {
+    pub name: String,
+    pub mesh: TriangleMesh,
+}
+
+/// A vertex of a face, as indices into the position, texture coordinate and
+/// normal lists of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FaceVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+/// Faces collected for a single group and material.
+struct GroupBuilder {
+    name: String,
+    material: Arc<dyn Material + Send + Sync>,
+    triangles: Vec<[FaceVertex; 3]>,
+}
+
+/// Load an OBJ file, along with any MTL files it references relative to it.
+/// Faces that don't use an MTL material use the default material instead.
+pub fn load_obj(
+    path: &Path,
+    default_material: Option<Arc<dyn Material + Send + Sync>>,
+) -> Result<Vec<ObjGroup>, ObjError> {
+    let file = path.display().to_string();
+    let src = fs::read_to_string(path)
+        .map_err(|e| ObjError::new(&file, None, format!("unable to read file: {}", e)))?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let load_mtl = |name: &str| -> Result<MaterialLibrary, ObjError> {
+        let mtl_path = directory.join(name);
+        let mtl_file = mtl_path.display().to_string();
+        let mtl_src = fs::read_to_string(&mtl_path)
+            .map_err(|e| ObjError::new(&mtl_file, None, format!("unable to read file: {}", e)))?;
+        parse_mtl(&mtl_src, &mtl_file)
+    };
+
+    parse_obj(&src, &file, &load_mtl, default_material)
+}
+
+type MaterialLibrary = HashMap<String, Arc<dyn Material + Send + Sync>>;
+
+/// Parse the contents of an OBJ file. `file` is only used for error messages.
+fn parse_obj(
+    src: &str,
+    file: &str,
+    load_mtl: &dyn Fn(&str) -> Result<MaterialLibrary, ObjError>,
+    default_material: Option<Arc<dyn Material + Send + Sync>>,
+) -> Result<Vec<ObjGroup>, ObjError> {
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut materials = MaterialLibrary::new();
+
+    let mut groups: Vec<GroupBuilder> = Vec::new();
+    let mut group_lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+    let mut group_name = String::from("default");
+    let mut material_name: Option<String> = None;
+
+    for (line_index, line) in src.lines().enumerate() {
+        let line_number = line_index + 1;
+        let error = |message: String| ObjError::new(file, Some(line_number), message);
+
+        // Strip comments.
+        let line = match line.find('#') {
+            Some(index) => &line[..index],
+            None => line,
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let v = parse_floats(&args, 3, "v").map_err(error)?;
+                positions.push(Point3::new(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let vt = parse_floats(&args, 1, "vt").map_err(error)?;
+                uvs.push((vt[0], vt.get(1).copied().unwrap_or(0.0)));
+            }
+            "vn" => {
+                let n = parse_floats(&args, 3, "vn").map_err(error)?;
+                normals.push(Vec3::new(n[0], n[1], n[2]));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error(format!(
+                        "a face needs at least 3 vertices, got {}",
+                        args.len()
+                    )));
+                }
+
+                let vertices = args
+                    .iter()
+                    .map(|arg| parse_face_vertex(arg, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(error)?;
+
+                let key = (group_name.clone(), material_name.clone());
+                let index = match group_lookup.get(&key) {
+                    Some(index) => *index,
+                    None => {
+                        let material = match &material_name {
+                            Some(name) => materials[name].clone(),
+                            None => default_material.clone().ok_or_else(|| {
+                                error(
+                                    "face has no material, use usemtl or give the mesh a material"
+                                        .to_string(),
+                                )
+                            })?,
+                        };
+                        groups.push(GroupBuilder {
+                            name: group_name.clone(),
+                            material,
+                            triangles: Vec::new(),
+                        });
+                        group_lookup.insert(key, groups.len() - 1);
+                        groups.len() - 1
+                    }
+                };
+
+                // Triangulate the polygon as a fan around the first vertex.
+                for i in 1..vertices.len() - 1 {
+                    groups[index]
+                        .triangles
+                        .push([vertices[0], vertices[i], vertices[i + 1]]);
+                }
+            }
+            "g" | "o" => {
+                group_name = if args.is_empty() {
+                    String::from("default")
+                } else {
+                    args.join(" ")
+                };
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                if !materials.contains_key(&name) {
+                    return Err(error(format!("unknown material '{}'", name)));
+                }
+                material_name = Some(name);
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err(error("mtllib needs a file name".to_string()));
+                }
+                for name in args.iter() {
+                    materials.extend(load_mtl(name)?);
+                }
+            }
+            // Smoothing groups, lines and points are not supported.
+            "s" | "l" | "p" => {}
+            _ => return Err(error(format!("unknown statement '{}'", keyword))),
+        }
+    }
+
+    Ok(groups
+        .into_iter()
+        .filter(|group| !group.triangles.is_empty())
+        .map(|group| build_group(group, &positions, &uvs, &normals))
+        .collect())
+}
+
+/// Build a mesh for a group, with one vertex for every unique combination of
+/// position, texture coordinate and normal the faces use.
+fn build_group(
+    group: GroupBuilder,
+    positions: &[Point3],
+    uvs: &[(f64, f64)],
+    normals: &[Vec3],
+) -> ObjGroup {
+    let vertices = || group.triangles.iter().flatten();
+    let has_uvs = vertices().all(|v| v.uv.is_some());
+    let has_normals = vertices().all(|v| v.normal.is_some());
+
+    let mut mesh = MeshData {
+        positions: Vec::new(),
+        normals: if has_normals { Some(Vec::new()) } else { None },
+        uvs: if has_uvs { Some(Vec::new()) } else { None },
+    };
+    let mut lookup: HashMap<FaceVertex, usize> = HashMap::new();
+    let mut triangles = Vec::with_capacity(group.triangles.len());
+
+    for triangle in group.triangles.iter() {
+        let mut indices = [0; 3];
+        for (i, vertex) in triangle.iter().enumerate() {
+            indices[i] = *lookup.entry(*vertex).or_insert_with(|| {
+                mesh.positions.push(positions[vertex.position]);
+                if let Some(mesh_normals) = &mut mesh.normals {
+                    mesh_normals.push(normals[vertex.normal.unwrap()]);
+                }
+                if let Some(mesh_uvs) = &mut mesh.uvs {
+                    mesh_uvs.push(uvs[vertex.uv.unwrap()]);
+                }
+                mesh.positions.len() - 1
+            });
+        }
+        triangles.push(indices);
+    }
+
+    ObjGroup {
+        name: group.name,
+        mesh: TriangleMesh::new(mesh, &triangles, group.material),
+    }
+}
+
+fn parse_floats(args: &[&str], min_count: usize, keyword: &str) -> Result<Vec<f64>, String> {
+    if args.len() < min_count {
+        return Err(format!(
+            "{} needs at least {} values, got {}",
+            keyword,
+            min_count,
+            args.len()
+        ));
+    }
+
+    args.iter()
+        .map(|arg| {
+            arg.parse::<f64>()
+                .map_err(|_| format!("'{}' is not a valid number", arg))
+        })
+        .collect()
+}
+
+/// Resolve a 1 based (or negative, relative to the end) OBJ index into a 0
+/// based index into a list of the given length.
+fn resolve_index(index: &str, count: usize, kind: &str) -> Result<usize, String> {
+    let value = index
+        .parse::<i64>()
+        .map_err(|_| format!("'{}' is not a valid {} index", index, kind))?;
+
+    let resolved = if value > 0 {
+        value - 1
+    } else {
+        count as i64 + value
+    };
+
+    if value == 0 || resolved < 0 || resolved >= count as i64 {
+        Err(format!(
+            "{} index {} is out of range ({} defined)",
+            kind, value, count
+        ))
+    } else {
+        Ok(resolved as usize)
+    }
+}
+
+/// Parse a face vertex in one of the forms v, v/vt, v//vn, or v/vt/vn.
+fn parse_face_vertex(
+    arg: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<FaceVertex, String> {
+    let mut parts = arg.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), position_count, "vertex")?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, uv_count, "texture coordinate")?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, normal_count, "normal")?),
+    };
+
+    if parts.next().is_some() {
+        return Err(format!("'{}' is not a valid face vertex", arg));
+    }
+
+    Ok(FaceVertex {
+        position,
+        uv,
+        normal,
+    })
+}
+
+/// MTL parameters the renderer uses, with the defaults from the MTL spec.
+struct MtlParams {
+    diffuse: Color,
+    specular: Color,
+    shininess: f64,
+    ior: f64,
+    dissolve: f64,
+    illum: i32,
+}
+
+impl Default for MtlParams {
+    fn default() -> Self {
+        MtlParams {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            illum: 2,
+        }
+    }
+}
+
+impl MtlParams {
+    fn build(&self) -> Arc<dyn Material + Send + Sync> {
+        let max_component = |c: Color| f64::max(c.x(), f64::max(c.y(), c.z()));
+        let refractive_illum = matches!(self.illum, 4 | 6 | 7 | 9);
+
+        if self.dissolve < 1.0 || refractive_illum {
+            Dielectric::new(self.ior)
+        } else if max_component(self.specular) > max_component(self.diffuse) {
+            // Map the Phong exponent to a rough equivalent fuzz.
+            let fuzz = f64::sqrt(2.0 / (self.shininess + 2.0));
+            Metal::new(self.specular, fuzz)
+        } else {
+            Lambertian::new(self.diffuse)
+        }
+    }
+}
+
+/// Parse the contents of an MTL file into materials by name. `file` is only
+/// used for error messages.
+fn parse_mtl(src: &str, file: &str) -> Result<MaterialLibrary, ObjError> {
+    let mut library = MaterialLibrary::new();
+    let mut current: Option<(String, MtlParams)> = None;
+
+    for (line_index, line) in src.lines().enumerate() {
+        let line_number = line_index + 1;
+        let error = |message: String| ObjError::new(file, Some(line_number), message);
+
+        let line = match line.find('#') {
+            Some(index) => &line[..index],
+            None => line,
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, params)) = current.take() {
+                library.insert(name, params.build());
+            }
+            if args.is_empty() {
+                return Err(error("newmtl needs a material name".to_string()));
+            }
+            current = Some((args.join(" "), MtlParams::default()));
+            continue;
+        }
+
+        let params = match &mut current {
+            Some((_, params)) => params,
+            None => return Err(error(format!("'{}' appears before newmtl", keyword))),
+        };
+
+        let color = |args: &[&str]| -> Result<Color, ObjError> {
+            let c = parse_floats(args, 1, keyword).map_err(error)?;
+            // A single value applies to all three channels.
+            Ok(match c.as_slice() {
+                [v] => Color::new(*v, *v, *v),
+                _ if c.len() >= 3 => Color::new(c[0], c[1], c[2]),
+                _ => return Err(error(format!("{} needs 1 or 3 values", keyword))),
+            })
+        };
+        let scalar = |args: &[&str]| -> Result<f64, ObjError> {
+            Ok(parse_floats(args, 1, keyword).map_err(error)?[0])
+        };
+
+        match keyword {
+            "Kd" => params.diffuse = color(&args)?,
+            "Ks" => params.specular = color(&args)?,
+            "Ns" => params.shininess = scalar(&args)?,
+            "Ni" => params.ior = scalar(&args)?,
+            "d" => params.dissolve = scalar(&args)?,
+            "Tr" => params.dissolve = 1.0 - scalar(&args)?,
+            "illum" => params.illum = scalar(&args)? as i32,
+            // Ambient, emissive, transmission filter, and texture maps are not
+            // supported.
+            _ => {}
+        }
+    }
+
+    if let Some((name, params)) = current.take() {
+        library.insert(name, params.build());
+    }
+
+    Ok(library)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hit::Hittable;
+    use crate::material::Lambertian;
+    use crate::obj::{parse_mtl, parse_obj, MaterialLibrary, ObjError};
+    use crate::random::Rng;
+    use crate::ray::Ray;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    const MTL: &str = "newmtl red\nKd 1 0 0\n\nnewmtl glass\nNi 1.5\nd 0.1\n";
+
+    fn load_mtl(name: &str) -> Result<MaterialLibrary, ObjError> {
+        assert_eq!(name, "test.mtl");
+        parse_mtl(MTL, name)
+    }
+
+    #[test]
+    fn parse_groups_and_quads() {
+        let mut rng = Rng::new(0);
+        let src = "mtllib test.mtl\n\
+                   v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                   vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                   vn 0 0 1\n\
+                   g front\nusemtl red\nf 1/1/1 2/2/1 3/3/1 4/4/1\n\
+                   g back\nusemtl glass\nf -1 -2 -3\n";
+
+        let groups = parse_obj(src, "test.obj", &load_mtl, None).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "front");
+        assert_eq!(groups[1].name, "back");
+
+        let r = Ray::new(Point3::new(0.25, 0.75, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = groups[0]
+            .mesh
+            .hit(&r, 0.001, f64::INFINITY, &mut rng)
+            .unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-9 && (rec.v - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let check = |src: &str, expected: &str| {
+            let error = parse_obj(src, "test.obj", &load_mtl, Some(material.clone()))
+                .err()
+                .unwrap();
+            assert_eq!(error.to_string(), expected);
+        };
+
+        check(
+            "v 0 0 0\nv 1 x 0\n",
+            "test.obj:2: 'x' is not a valid number",
+        );
+        check(
+            "v 0 0 0\nv 1 0 0\nf 1 2 3\n",
+            "test.obj:3: vertex index 3 is out of range (2 defined)",
+        );
+        check(
+            "mtllib test.mtl\nusemtl blue\n",
+            "test.obj:2: unknown material 'blue'",
+        );
+        check(
+            "v 0 0 0\nf 1 1\n",
+            "test.obj:2: a face needs at least 3 vertices, got 2",
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use crate::vec3::Vec3;
+
+/// An orthonormal basis, used to express directions relative to a normal.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    /// Build a basis whose w axis points along n.
+    pub fn build_from_w(n: &Vec3) -> Self {
+        let w = Vec3::unit_vector(*n);
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::unit_vector(Vec3::cross(&w, &a));
+        let u = Vec3::cross(&w, &v);
+
+        Onb { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+
+    /// Convert a direction in basis coordinates to world coordinates.
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
+    }
+}
@@ -0,0 +1,347 @@
+use crate::hdr;
+use crate::output::ImageFormat;
+use crate::scene::{CameraSettings, SceneSource};
+use crate::tile::{Region, TileOrder};
+use crate::vec3::Vec3;
+use std::path::PathBuf;
+use structopt::clap::{Error, ErrorKind};
+use structopt::StructOpt;
+
+/// Command line options for the renderer.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "raytracing", about = "Raytracing in one weekend, in rust.")]
+pub struct Options {
+    /// Image width in pixels
+    #[structopt(short, long, default_value = "1200", parse(try_from_str = parse_dimension))]
+    pub width: u32,
+
+    /// Image height in pixels. Overrides the aspect ratio if set
+    #[structopt(short = "H", long, parse(try_from_str = parse_dimension), conflicts_with = "aspect-ratio")]
+    pub height: Option<u32>,
+
+    /// Aspect ratio as width:height or a single number
+    #[structopt(long, default_value = "16:9", parse(try_from_str = parse_aspect_ratio))]
+    pub aspect_ratio: f64,
+
+    /// Number of samples per pixel
+    #[structopt(short, long, default_value = "100", parse(try_from_str = parse_positive))]
+    pub samples: u32,
+
+    /// Number of samples per pixel in each pass. The image is rendered in
+    /// passes, and the passes completed so far are saved if the render is
+    /// stopped early
+    #[structopt(long, default_value = "16", parse(try_from_str = parse_positive))]
+    pub pass_samples: u32,
+
+    /// Stop the render after this many seconds, dropping the pass in progress.
+    /// The first pass is always finished
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    pub time_limit: Option<f64>,
+
+    /// File to write a snapshot of the image so far to between passes, in
+    /// the format given by its extension
+    #[structopt(long, parse(from_os_str))]
+    pub snapshot: Option<PathBuf>,
+
+    /// Minimum number of seconds between snapshots
+    #[structopt(long, default_value = "30", parse(try_from_str = parse_seconds))]
+    pub snapshot_interval: f64,
+
+    /// File to save the progress of the render to between passes, and when
+    /// it stops, so that it can be resumed
+    #[structopt(long, parse(from_os_str))]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Minimum number of seconds between checkpoints
+    #[structopt(long, default_value = "300", parse(try_from_str = parse_seconds))]
+    pub checkpoint_interval: f64,
+
+    /// Carry on with the render saved in the checkpoint file. The scene and
+    /// settings must be the same, apart from the number of samples
+    #[structopt(long, requires = "checkpoint")]
+    pub resume: bool,
+
+    /// Width and height of the square tiles the image is rendered in
+    #[structopt(long, default_value = "32", parse(try_from_str = parse_positive))]
+    pub tile_size: u32,
+
+    /// Order to render the tiles in
+    #[structopt(long, default_value = "spiral", possible_values = TileOrder::NAMES)]
+    pub tile_order: TileOrder,
+
+    /// Only render the pixels from x0,y0 up to x1,y1, counting from the top
+    /// left. The rest of the image is left black
+    #[structopt(long)]
+    pub region: Option<Region>,
+
+    /// Sample pixels adaptively, stopping once the standard error of a pixel's
+    /// brightness is less than this fraction of it. Noisy pixels still take no
+    /// more than --samples samples
+    #[structopt(long, parse(try_from_str = parse_positive_f64))]
+    pub adaptive_threshold: Option<f64>,
+
+    /// Number of samples every pixel takes before it may stop adaptively
+    #[structopt(long, default_value = "16", parse(try_from_str = parse_positive))]
+    pub adaptive_min_samples: u32,
+
+    /// File to write an image of the number of samples each pixel took to, in
+    /// the format given by its extension
+    #[structopt(long, parse(from_os_str))]
+    pub heatmap: Option<PathBuf>,
+
+    /// Maximum number of ray bounces
+    #[structopt(short = "d", long, default_value = "50", parse(try_from_str = parse_positive))]
+    pub max_depth: u32,
+
+    /// Number of bounces before paths may be ended early by Russian roulette,
+    /// in proportion to how little light they still carry
+    #[structopt(long, default_value = "5")]
+    pub min_depth: u32,
+
+    /// Output file. The format is chosen by the extension (.png, .ppm, or the
+    /// high dynamic range .pfm, .hdr and .exr). The image is written to stdout
+    /// if not set
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// Output image format, overriding the output file extension. Defaults to
+    /// p3 when writing to stdout
+    #[structopt(long, possible_values = ImageFormat::NAMES)]
+    pub format: Option<ImageFormat>,
+
+    /// Scene to render, either the name of a built in scene (random,
+    /// bouncing_spheres, simple_light) or the path to a .toml scene file
+    #[structopt(long, default_value = "random")]
+    pub scene: SceneSource,
+
+    /// Camera position as x,y,z
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    pub lookfrom: Option<Vec3>,
+
+    /// Point the camera looks at as x,y,z
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    pub lookat: Option<Vec3>,
+
+    /// Camera up vector as x,y,z
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    pub vup: Option<Vec3>,
+
+    /// Vertical field of view in degrees
+    #[structopt(long)]
+    pub vfov: Option<f64>,
+
+    /// Camera lens aperture
+    #[structopt(long)]
+    pub aperture: Option<f64>,
+
+    /// Distance to the focus plane
+    #[structopt(long)]
+    pub focus_dist: Option<f64>,
+
+    /// Time the camera shutter opens
+    #[structopt(long)]
+    pub time0: Option<f64>,
+
+    /// Time the camera shutter closes
+    #[structopt(long)]
+    pub time1: Option<f64>,
+
+    /// Number of render threads. Defaults to the number of logical cores
+    #[structopt(short = "j", long, parse(try_from_str = parse_positive))]
+    pub threads: Option<u32>,
+
+    /// Seed for all random numbers. Renders with the same seed and settings
+    /// are identical
+    #[structopt(long, default_value = "0")]
+    pub seed: u64,
+}
+
+impl Options {
+    /// Parse the options from the command line, exiting with an error message
+    /// if any of them are invalid.
+    pub fn from_args_validated() -> Self {
+        let options = Options::from_args();
+
+        if let Err(message) = options.validate() {
+            exit_with_error(&message);
+        }
+
+        options
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.image_height() < 2 {
+            return Err(format!(
+                "an image width of {} with aspect ratio {} gives an image less than 2 pixels high",
+                self.width, self.aspect_ratio
+            ));
+        }
+
+        // The framebuffers hold every pixel, so the image is limited to the
+        // size of the images that can be read.
+        if hdr::checked_pixel_count(self.width as usize, self.image_height() as usize).is_err() {
+            return Err(format!(
+                "a {}x{} image is too large to render",
+                self.width,
+                self.image_height()
+            ));
+        }
+
+        if let Some(region) = &self.region {
+            if !self.image_region().contains(region) {
+                return Err(format!(
+                    "the region {},{},{},{} is not inside the {}x{} image",
+                    region.x0,
+                    region.y0,
+                    region.x1,
+                    region.y1,
+                    self.width,
+                    self.image_height()
+                ));
+            }
+        }
+
+        if self.format.is_none() {
+            if let Some(path) = &self.output {
+                if ImageFormat::from_path(path).is_none() {
+                    return Err(format!(
+                        "unable to determine the image format of {}, use a known image extension or set --format",
+                        path.display()
+                    ));
+                }
+            }
+        }
+
+        for (name, path) in [("snapshot", &self.snapshot), ("heatmap", &self.heatmap)].iter() {
+            if let Some(path) = path {
+                if ImageFormat::from_path(path).is_none() {
+                    return Err(format!(
+                        "unable to determine the image format of {} {}, use a known image extension",
+                        name,
+                        path.display()
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The format to write the output image in.
+    pub fn output_format(&self) -> ImageFormat {
+        self.format
+            .or_else(|| self.output.as_deref().and_then(ImageFormat::from_path))
+            .unwrap_or(ImageFormat::PpmAscii)
+    }
+
+    pub fn image_height(&self) -> u32 {
+        match self.height {
+            Some(height) => height,
+            None => (self.width as f64 / self.aspect_ratio) as u32,
+        }
+    }
+
+    /// The part of the image to render, the whole image unless a region is
+    /// set.
+    pub fn render_region(&self) -> Region {
+        self.region.unwrap_or_else(|| self.image_region())
+    }
+
+    fn image_region(&self) -> Region {
+        Region::new(0, 0, self.width as usize, self.image_height() as usize)
+    }
+
+    /// The aspect ratio the camera should use.
+    pub fn image_aspect_ratio(&self) -> f64 {
+        match self.height {
+            Some(height) => self.width as f64 / height as f64,
+            None => self.aspect_ratio,
+        }
+    }
+
+    /// Apply any camera overrides on top of the scene's camera.
+    pub fn camera_settings(&self, scene_camera: CameraSettings) -> CameraSettings {
+        CameraSettings {
+            lookfrom: self.lookfrom.unwrap_or(scene_camera.lookfrom),
+            lookat: self.lookat.unwrap_or(scene_camera.lookat),
+            vup: self.vup.unwrap_or(scene_camera.vup),
+            vfov: self.vfov.unwrap_or(scene_camera.vfov),
+            aperture: self.aperture.unwrap_or(scene_camera.aperture),
+            focus_dist: self.focus_dist.unwrap_or(scene_camera.focus_dist),
+            time0: self.time0.unwrap_or(scene_camera.time0),
+            time1: self.time1.unwrap_or(scene_camera.time1),
+        }
+    }
+}
+
+/// Print a command line validation error and exit.
+pub fn exit_with_error(message: &str) -> ! {
+    Error::with_description(message, ErrorKind::ValueValidation).exit()
+}
+
+fn parse_positive(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(0) => Err("value must be greater than zero".to_string()),
+        Ok(value) => Ok(value),
+        Err(e) => Err(format!("'{}' is not a valid positive integer: {}", s, e)),
+    }
+}
+
+fn parse_dimension(s: &str) -> Result<u32, String> {
+    let value = parse_positive(s)?;
+    if value < 2 {
+        Err("image dimensions must be at least 2 pixels".to_string())
+    } else {
+        Ok(value)
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+        _ => Err(format!("'{}' is not a valid number of seconds", s)),
+    }
+}
+
+fn parse_positive_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        _ => Err(format!("'{}' is not a valid positive number", s)),
+    }
+}
+
+/// Parse an aspect ratio in the form "16:9" or "1.777".
+fn parse_aspect_ratio(s: &str) -> Result<f64, String> {
+    let ratio = match s.find(':') {
+        Some(index) => {
+            let parse = |part: &str| {
+                part.trim()
+                    .parse::<f64>()
+                    .map_err(|_| format!("'{}' is not a valid aspect ratio", s))
+            };
+            parse(&s[..index])? / parse(&s[index + 1..])?
+        }
+        None => s
+            .parse::<f64>()
+            .map_err(|_| format!("'{}' is not a valid aspect ratio", s))?,
+    };
+
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(format!("aspect ratio must be positive, got '{}'", s))
+    }
+}
+
+/// Parse a vector in the form "x,y,z".
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let parts: Vec<_> = s
+        .split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect();
+
+    match parts.as_slice() {
+        [Ok(x), Ok(y), Ok(z)] => Ok(Vec3::new(*x, *y, *z)),
+        _ => Err(format!("'{}' is not a vector, expected x,y,z", s)),
+    }
+}
@@ -0,0 +1,175 @@
+use crate::hdr;
+use crate::vec3::Color;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Supported output image formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    /// 8-bit RGB PNG.
+    Png,
+    /// Binary (P6) PPM.
+    Ppm,
+    /// ASCII (P3) PPM.
+    PpmAscii,
+    /// Little endian float PFM.
+    Pfm,
+    /// Radiance RGBE .hdr.
+    Hdr,
+    /// Uncompressed float OpenEXR.
+    Exr,
+}
+
+impl ImageFormat {
+    pub const NAMES: &'static [&'static str] = &["png", "ppm", "p3", "pfm", "hdr", "exr"];
+
+    /// Determine the format from the extension of a path.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pfm" => Some(ImageFormat::Pfm),
+            "hdr" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(ImageFormat::Png),
+            "ppm" => Ok(ImageFormat::Ppm),
+            "p3" => Ok(ImageFormat::PpmAscii),
+            "pfm" => Ok(ImageFormat::Pfm),
+            "hdr" => Ok(ImageFormat::Hdr),
+            "exr" => Ok(ImageFormat::Exr),
+            _ => Err(format!(
+                "unknown image format '{}', expected one of: {}",
+                s,
+                ImageFormat::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// A rendered image in linear color, stored row by row from the top left.
+/// Each pixel holds the average of its samples.
+#[derive(Debug, Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(width * height, pixels.len());
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// The sRGB encoded 8-bit rgb values of the image.
+    fn rgb8_bytes(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|c| c.to_rgb8().to_vec())
+            .collect()
+    }
+}
+
+/// Write the image in the given format. The writer should be buffered.
+pub fn write_image(image: &Image, format: ImageFormat, writer: &mut dyn Write) -> io::Result<()> {
+    match format {
+        ImageFormat::Png => write_png(image, writer),
+        ImageFormat::Ppm => write_ppm(image, writer),
+        ImageFormat::PpmAscii => write_ppm_ascii(image, writer),
+        ImageFormat::Pfm => hdr::write_pfm(image, writer),
+        ImageFormat::Hdr => hdr::write_radiance_hdr(image, writer),
+        ImageFormat::Exr => hdr::write_exr(image, writer),
+    }
+}
+
+fn write_png(image: &Image, writer: &mut dyn Write) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    // Mark the image as sRGB, with the matching gamma for older readers.
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_chunk(*b"sRGB", &[0])?;
+    png_writer.write_chunk(*b"gAMA", &45455u32.to_be_bytes())?;
+    png_writer.write_image_data(&image.rgb8_bytes())?;
+    Ok(())
+}
+
+fn write_ppm(image: &Image, writer: &mut dyn Write) -> io::Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", image.width, image.height)?;
+    writer.write_all(&image.rgb8_bytes())
+}
+
+fn write_ppm_ascii(image: &Image, writer: &mut dyn Write) -> io::Result<()> {
+    write!(writer, "P3\n{} {}\n255\n", image.width, image.height)?;
+    for color in image.pixels.iter() {
+        let [r, g, b] = color.to_rgb8();
+        writeln!(writer, "{} {} {}", r, g, b)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::{write_image, Image, ImageFormat};
+    use crate::vec3::Color;
+
+    fn test_image() -> Image {
+        Image::new(
+            2,
+            1,
+            vec![Color::new(0.0, 0.25, 1.0), Color::new(2.0, 0.0, 0.0)],
+        )
+    }
+
+    #[test]
+    fn ppm_formats() {
+        let mut ascii = Vec::new();
+        write_image(&test_image(), ImageFormat::PpmAscii, &mut ascii).unwrap();
+        assert_eq!(
+            String::from_utf8(ascii).unwrap(),
+            "P3\n2 1\n255\n0 137 255\n255 0 0\n"
+        );
+
+        let mut binary = Vec::new();
+        write_image(&test_image(), ImageFormat::Ppm, &mut binary).unwrap();
+        assert_eq!(&binary[..11], b"P6\n2 1\n255\n");
+        assert_eq!(&binary[11..], &[0, 137, 255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn png_signature() {
+        let mut png = Vec::new();
+        write_image(&test_image(), ImageFormat::Png, &mut png).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert!(png.windows(4).any(|chunk| chunk == b"sRGB"));
+    }
+}
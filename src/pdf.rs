@@ -0,0 +1,231 @@
+//! Probability density functions over directions, used to importance sample
+//! the directions rays are scattered in.
+
+use crate::hit::Hittable;
+use crate::onb::Onb;
+use crate::random::Rng;
+use crate::utility;
+use crate::vec3::{Point3, Vec3};
+
+pub trait Pdf {
+    /// Returns the probability density, over solid angle, of generating the
+    /// direction.
+    fn value(&self, direction: &Vec3) -> f64;
+
+    /// Choose a random direction distributed according to the pdf.
+    fn generate(&self, rng: &mut Rng) -> Vec3;
+}
+
+impl std::fmt::Debug for dyn Pdf {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "dyn Pdf")
+    }
+}
+
+/// A random direction on the hemisphere around +z, cosine weighted.
+pub fn random_cosine_direction(rng: &mut Rng) -> Vec3 {
+    let r1 = rng.random_f64();
+    let r2 = rng.random_f64();
+
+    let phi = 2.0 * utility::PI * r1;
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
+    let z = f64::sqrt(1.0 - r2);
+
+    Vec3::new(x, y, z)
+}
+
+/// A random direction around +z within the cone where cos(theta) is at least
+/// `cos_theta_max`, uniform over solid angle.
+fn random_in_cone(rng: &mut Rng, cos_theta_max: f64) -> Vec3 {
+    let r1 = rng.random_f64();
+    let r2 = rng.random_f64();
+
+    let z = 1.0 - r2 * (1.0 - cos_theta_max);
+    let phi = 2.0 * utility::PI * r1;
+    let sin_theta = f64::sqrt(1.0 - z * z);
+
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+}
+
+/// Directions over the hemisphere around a normal, weighted by the cosine of
+/// their angle to it. This is how Lambertian surfaces scatter.
+#[derive(Debug)]
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(w: &Vec3) -> Self {
+        CosinePdf {
+            uvw: Onb::build_from_w(w),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = Vec3::dot(&Vec3::unit_vector(*direction), &self.uvw.w());
+        f64::max(cosine / utility::PI, 0.0)
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Vec3 {
+        self.uvw.local(&random_cosine_direction(rng))
+    }
+}
+
+/// Directions spread uniformly over the whole sphere.
+#[derive(Debug)]
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * utility::PI)
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Vec3 {
+        Vec3::random_unit_vector(rng)
+    }
+}
+
+/// Directions spread uniformly over a cone around an axis, like the
+/// directions towards a sphere.
+#[derive(Debug)]
+pub struct ConePdf {
+    uvw: Onb,
+    cos_theta_max: f64,
+}
+
+impl ConePdf {
+    /// Create a cone around w, including directions whose angle to w has a
+    /// cosine of at least `cos_theta_max`.
+    pub fn new(w: &Vec3, cos_theta_max: f64) -> Self {
+        ConePdf {
+            uvw: Onb::build_from_w(w),
+            cos_theta_max,
+        }
+    }
+}
+
+impl Pdf for ConePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = Vec3::dot(&Vec3::unit_vector(*direction), &self.uvw.w());
+        if cosine < self.cos_theta_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * utility::PI * (1.0 - self.cos_theta_max))
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Vec3 {
+        self.uvw.local(&random_in_cone(rng, self.cos_theta_max))
+    }
+}
+
+/// Directions from an origin towards points on an object, as sampled by the
+/// object itself. Objects that can't be sampled give no directions, so this
+/// isn't a `Pdf` that always has one.
+pub struct HittablePdf<'a> {
+    objects: &'a (dyn Hittable + Send + Sync),
+    origin: Point3,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(objects: &'a (dyn Hittable + Send + Sync), origin: Point3) -> Self {
+        HittablePdf { objects, origin }
+    }
+
+    /// Returns the probability density, over solid angle, of generating the
+    /// direction.
+    pub fn value(&self, direction: &Vec3) -> f64 {
+        self.objects.pdf_value(&self.origin, direction)
+    }
+
+    /// Choose a random direction towards the object, or None if it can't be
+    /// sampled.
+    pub fn generate(&self, rng: &mut Rng) -> Option<Vec3> {
+        Some(self.objects.random_point(&self.origin, rng)? - self.origin)
+    }
+}
+
+/// An even mix of two pdfs.
+pub struct MixturePdf<'a> {
+    p: [&'a dyn Pdf; 2],
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(p0: &'a dyn Pdf, p1: &'a dyn Pdf) -> Self {
+        MixturePdf { p: [p0, p1] }
+    }
+}
+
+impl<'a> Pdf for MixturePdf<'a> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        0.5 * self.p[0].value(direction) + 0.5 * self.p[1].value(direction)
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Vec3 {
+        if rng.random_f64() < 0.5 {
+            self.p[0].generate(rng)
+        } else {
+            self.p[1].generate(rng)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::material::Lambertian;
+    use crate::pdf::{ConePdf, CosinePdf, HittablePdf, MixturePdf, Pdf};
+    use crate::random::Rng;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    /// Estimate the integral of a density over the sphere of directions by
+    /// uniform sampling.
+    fn integrate(value: &dyn Fn(&Vec3) -> f64, rng: &mut Rng) -> f64 {
+        let n = 200_000;
+        let sum: f64 = (0..n).map(|_| value(&Vec3::random_unit_vector(rng))).sum();
+        4.0 * crate::utility::PI * sum / n as f64
+    }
+
+    #[test]
+    fn pdfs_integrate_to_one() {
+        let mut rng = Rng::new(1);
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, -3.0),
+            1.0,
+            Lambertian::new(Color::new(1.0, 1.0, 1.0)),
+        );
+
+        let cosine = CosinePdf::new(&Vec3::new(1.0, 1.0, 0.0));
+        let towards_sphere = HittablePdf::new(&sphere, Point3::new(0.0, 0.0, 0.0));
+        let cone = ConePdf::new(&Vec3::new(0.0, -1.0, 0.0), 0.5);
+        let mixture = MixturePdf::new(&cosine, &cone);
+
+        for pdf in [&cosine as &dyn Pdf, &mixture, &cone].iter() {
+            let integral = integrate(&|direction| pdf.value(direction), &mut rng);
+            assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+        }
+        let integral = integrate(&|direction| towards_sphere.value(direction), &mut rng);
+        assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+    }
+
+    #[test]
+    fn generated_directions_have_density() {
+        let mut rng = Rng::new(2);
+        let sphere = Sphere::new(
+            Point3::new(2.0, 1.0, 0.0),
+            0.5,
+            Lambertian::new(Color::new(1.0, 1.0, 1.0)),
+        );
+        let towards_sphere = HittablePdf::new(&sphere, Point3::new(0.0, 0.0, 0.0));
+        let cosine = CosinePdf::new(&Vec3::new(0.0, 1.0, 0.0));
+
+        for _ in 0..1000 {
+            let direction = towards_sphere.generate(&mut rng).unwrap();
+            assert!(towards_sphere.value(&direction) > 0.0);
+            let direction = cosine.generate(&mut rng);
+            assert!(direction.y() >= 0.0 && cosine.value(&direction) >= 0.0);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::random::Rng;
+use crate::vec3::{Point3, Vec3};
+
+const POINT_COUNT: usize = 256;
+
+/// Perlin gradient noise. A random gradient is placed at every integer
+/// lattice point and the noise between them is smoothly interpolated, giving
+/// values in about [-1, 1] that vary on a scale of 1.
+#[derive(Debug)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(rng: &mut Rng) -> Self {
+        let gradients = (0..POINT_COUNT)
+            .map(|_| Vec3::unit_vector(Vec3::random_range(rng, -1.0, 1.0)))
+            .collect();
+
+        Perlin {
+            gradients,
+            perm_x: Perlin::generate_perm(rng),
+            perm_y: Perlin::generate_perm(rng),
+            perm_z: Perlin::generate_perm(rng),
+        }
+    }
+
+    /// A random permutation of 0..POINT_COUNT.
+    fn generate_perm(rng: &mut Rng) -> Vec<usize> {
+        let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+        for i in (1..POINT_COUNT).rev() {
+            let target = usize::min((rng.random_f64() * (i + 1) as f64) as usize, i);
+            perm.swap(i, target);
+        }
+        perm
+    }
+
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let floor = |x: f64| (x.floor(), x - x.floor());
+        let (i, u) = floor(p.x());
+        let (j, v) = floor(p.y());
+        let (k, w) = floor(p.z());
+        let (i, j, k) = (i as i64, j as i64, k as i64);
+
+        let mut gradients = [[[Vec3::default(); 2]; 2]; 2];
+        for (di, plane) in gradients.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, gradient) in row.iter_mut().enumerate() {
+                    let index = |perm: &[usize], n: i64, d: usize| {
+                        perm[((n + d as i64) & (POINT_COUNT as i64 - 1)) as usize]
+                    };
+                    *gradient = self.gradients[index(&self.perm_x, i, di)
+                        ^ index(&self.perm_y, j, dj)
+                        ^ index(&self.perm_z, k, dk)];
+                }
+            }
+        }
+
+        Perlin::interpolate(&gradients, u, v, w)
+    }
+
+    /// Trilinear interpolation of the contribution of each corner's gradient,
+    /// with Hermite smoothing of the weights so the noise has no visible grid.
+    fn interpolate(gradients: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+        let (uu, vv, ww) = (smooth(u), smooth(v), smooth(w));
+        let weight = |t: f64, n: usize| if n == 1 { t } else { 1.0 - t };
+
+        let mut accum = 0.0;
+        for (i, plane) in gradients.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, gradient) in row.iter().enumerate() {
+                    let offset = Vec3::new(u - i as f64, v - j as f64, w - k as f64);
+                    accum += weight(uu, i)
+                        * weight(vv, j)
+                        * weight(ww, k)
+                        * Vec3::dot(gradient, &offset);
+                }
+            }
+        }
+        accum
+    }
+
+    /// The sum of noise at increasing frequencies and decreasing amplitudes,
+    /// for a turbulent look.
+    pub fn turbulence(&self, p: &Point3, depth: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(&p);
+            weight *= 0.5;
+            p = 2.0 * p;
+        }
+
+        accum.abs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::perlin::Perlin;
+    use crate::random::Rng;
+    use crate::vec3::Point3;
+
+    #[test]
+    fn noise_is_smooth_and_zero_on_the_lattice() {
+        let perlin = Perlin::new(&mut Rng::new(0));
+
+        assert_eq!(perlin.noise(&Point3::new(3.0, -2.0, 7.0)), 0.0);
+
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            let p = 10.0 * Point3::random_range(&mut rng, -1.0, 1.0);
+            let value = perlin.noise(&p);
+            assert!(value.abs() <= 1.0);
+
+            let nearby = perlin.noise(&(p + Point3::new(1e-4, 1e-4, 1e-4)));
+            assert!((value - nearby).abs() < 1e-3);
+        }
+    }
+}
@@ -4,12 +4,19 @@ use crate::vec3::{Point3, Vec3};
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
+    /// The time the ray was sent, for objects that move while the camera
+    /// shutter is open.
+    time: f64,
 }
 
 #[allow(dead_code)]
 impl Ray {
-    pub fn new(origin: Point3, direction: Vec3) -> Self {
-        Ray { origin, direction }
+    pub fn new(origin: Point3, direction: Vec3, time: f64) -> Self {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
     }
 
     pub fn origin(&self) -> Point3 {
@@ -20,6 +27,10 @@ impl Ray {
         self.direction
     }
 
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + Point3::from(t * self.direction)
     }
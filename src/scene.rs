@@ -0,0 +1,290 @@
+use crate::camera::Camera;
+use crate::environment::{Environment, SkyGradient, SolidBackground};
+use crate::hit::HittableList;
+use crate::material::Material;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
+use crate::moving_sphere::MovingSphere;
+use crate::random::Rng;
+use crate::scene_file::{load_scene, SceneError};
+use crate::sphere::Sphere;
+use crate::texture::CheckerTexture;
+use crate::vec3::{Color, Point3, Vec3};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Parameters used to construct the camera for a scene.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraSettings {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    /// Vertical field of view in degrees.
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    /// Shutter open and close times, objects in motion are blurred over
+    /// this interval.
+    pub time0: f64,
+    pub time1: f64,
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect_ratio: f64) -> Camera {
+        Camera::new(
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            self.focus_dist,
+            self.time0,
+            self.time1,
+        )
+    }
+
+    /// Check that the settings describe a usable camera.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.vfov > 0.0 && self.vfov < 180.0) {
+            return Err(format!(
+                "vfov must be between 0 and 180 degrees, got {}",
+                self.vfov
+            ));
+        }
+
+        if !self.aperture.is_finite() || self.aperture < 0.0 {
+            return Err(format!(
+                "aperture must not be negative, got {}",
+                self.aperture
+            ));
+        }
+
+        if !self.focus_dist.is_finite() || self.focus_dist <= 0.0 {
+            return Err(format!(
+                "focus distance must be positive, got {}",
+                self.focus_dist
+            ));
+        }
+
+        if !(self.time0.is_finite() && self.time1.is_finite() && self.time0 <= self.time1) {
+            return Err(format!(
+                "the shutter must close after it opens, got times {} to {}",
+                self.time0, self.time1
+            ));
+        }
+
+        let view = self.lookfrom - self.lookat;
+        if view.length_squared() == 0.0 {
+            return Err("lookfrom and lookat must be different points".to_string());
+        }
+
+        if Vec3::cross(&self.vup, &view).length_squared() == 0.0 {
+            return Err("vup must not be parallel to the view direction".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// A world to render along with the camera it should be viewed from.
+pub struct Scene {
+    pub world: HittableList,
+    pub camera: CameraSettings,
+    /// The light seen by rays that escape the scene.
+    pub environment: Arc<dyn Environment + Send + Sync>,
+    /// Light emitting objects in the world, which rays are sent towards.
+    pub lights: HittableList,
+}
+
+/// The built in scenes that can be selected on the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuiltinScene {
+    Random,
+    BouncingSpheres,
+    SimpleLight,
+}
+
+impl BuiltinScene {
+    pub const NAMES: &'static [&'static str] = &["random", "bouncing_spheres", "simple_light"];
+
+    /// Build the scene, using the seed for any random generation.
+    pub fn build(&self, seed: u64) -> Scene {
+        let mut rng = Rng::new(seed);
+        match self {
+            BuiltinScene::Random => random_scene(&mut rng, false),
+            BuiltinScene::BouncingSpheres => random_scene(&mut rng, true),
+            BuiltinScene::SimpleLight => simple_light_scene(),
+        }
+    }
+}
+
+/// Where to get the scene to render from, either a built in scene or a scene
+/// file on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneSource {
+    Builtin(BuiltinScene),
+    File(PathBuf),
+}
+
+impl SceneSource {
+    pub fn load(&self, seed: u64) -> Result<Scene, SceneError> {
+        match self {
+            SceneSource::Builtin(scene) => Ok(scene.build(seed)),
+            SceneSource::File(path) => load_scene(path),
+        }
+    }
+}
+
+impl FromStr for SceneSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(SceneSource::Builtin(BuiltinScene::Random)),
+            "bouncing_spheres" => Ok(SceneSource::Builtin(BuiltinScene::BouncingSpheres)),
+            "simple_light" => Ok(SceneSource::Builtin(BuiltinScene::SimpleLight)),
+            _ if s.ends_with(".toml") => Ok(SceneSource::File(PathBuf::from(s))),
+            _ => Err(format!(
+                "unknown scene '{}', expected a .toml scene file or one of: {}",
+                s,
+                BuiltinScene::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// The final scene from the book, a large number of small random spheres
+/// around three big ones. If `bouncing`, the small diffuse spheres move up
+/// while the camera shutter is open and the ground is checkered.
+pub fn random_scene(rng: &mut Rng, bouncing: bool) -> Scene {
+    let mut world = HittableList::default();
+
+    let ground_material = if bouncing {
+        Lambertian::with_texture(CheckerTexture::from_colors(
+            Color::new(0.2, 0.3, 0.1),
+            Color::new(0.9, 0.9, 0.9),
+            1.0,
+        ))
+    } else {
+        Lambertian::new(Color::new(0.5, 0.5, 0.5))
+    };
+    world.add(Sphere::new_arc(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    ));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = rng.random_f64();
+            let center = Point3::new(
+                a as f64 + 0.9 * rng.random_f64(),
+                0.2,
+                b as f64 + 0.9 * rng.random_f64(),
+            );
+
+            if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                if choose_mat < 0.8 && bouncing {
+                    // Diffuse, bouncing up while the shutter is open
+                    let albedo = Color::random(rng) * Color::random(rng);
+                    let center1 = center + Vec3::new(0.0, rng.random_f64_range(0.0, 0.5), 0.0);
+                    world.add(MovingSphere::new_arc(
+                        center,
+                        center1,
+                        0.0,
+                        1.0,
+                        0.2,
+                        Lambertian::new(albedo),
+                    ));
+                    continue;
+                }
+
+                let sphere_material: Arc<dyn Material + Send + Sync> = if choose_mat < 0.8 {
+                    // Diffuse
+                    let albedo = Color::random(rng) * Color::random(rng);
+                    Lambertian::new(albedo)
+                } else if choose_mat < 0.95 {
+                    // Metal
+                    let albedo = Color::random(rng);
+                    let fuzz = rng.random_f64_range(0.0, 0.5);
+                    Metal::new(albedo, fuzz)
+                } else {
+                    // Glass
+                    Dielectric::new(1.5)
+                };
+
+                world.add(Sphere::new_arc(center, 0.2, sphere_material));
+            }
+        }
+    }
+
+    let material1 = Dielectric::new(1.5);
+    world.add(Sphere::new_arc(Point3::new(0.0, 1.0, 0.0), 1.0, material1));
+
+    let material2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
+    world.add(Sphere::new_arc(Point3::new(-4.0, 1.0, 0.0), 1.0, material2));
+
+    let material3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
+    world.add(Sphere::new_arc(Point3::new(4.0, 1.0, 0.0), 1.0, material3));
+
+    let camera = CameraSettings {
+        lookfrom: Point3::new(13.0, 2.0, 3.0),
+        lookat: Point3::new(0.0, 0.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        vfov: 20.0,
+        aperture: 0.1,
+        focus_dist: 10.0,
+        time0: 0.0,
+        time1: if bouncing { 1.0 } else { 0.0 },
+    };
+
+    Scene {
+        world,
+        camera,
+        environment: SkyGradient::book(),
+        lights: HittableList::default(),
+    }
+}
+
+/// A sphere on a checkered ground lit only by a glowing sphere above it.
+pub fn simple_light_scene() -> Scene {
+    let mut world = HittableList::default();
+
+    let checker =
+        CheckerTexture::from_colors(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9), 1.0);
+    world.add(Sphere::new_arc(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Lambertian::with_texture(checker),
+    ));
+    world.add(Sphere::new_arc(
+        Point3::new(0.0, 2.0, 0.0),
+        2.0,
+        Lambertian::new(Color::new(0.4, 0.2, 0.1)),
+    ));
+
+    let mut lights = HittableList::default();
+    let light = DiffuseLight::new(Color::new(4.0, 4.0, 4.0));
+    let light = Sphere::new_arc(Point3::new(0.0, 7.0, 0.0), 2.0, light);
+    world.add(light.clone());
+    lights.add(light);
+
+    let camera = CameraSettings {
+        lookfrom: Point3::new(26.0, 3.0, 6.0),
+        lookat: Point3::new(0.0, 2.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        vfov: 20.0,
+        aperture: 0.0,
+        focus_dist: 10.0,
+        time0: 0.0,
+        time1: 0.0,
+    };
+
+    Scene {
+        world,
+        camera,
+        environment: SolidBackground::new(Color::new(0.0, 0.0, 0.0)),
+        lights,
+    }
+}
@@ -0,0 +1,1189 @@
+//! Loading of scenes described by TOML files.
+//!
+//! A scene file contains a `[camera]` table, either a `background` color or
+//! an `[environment]` table for the light from outside the scene (the sky
+//! gradient if neither is set), a set of named textures under
+//! `[textures.<name>]`, a set of named materials under `[materials.<name>]`,
+//! and a list of `[[objects]]` that refer to those materials by name.
+//! Materials take either a constant `albedo` or the name of a `texture`.
+//! Objects made of a `diffuse_light` material are sampled as lights. An
+//! object with a `density` is filled with a volume, like fog or smoke, that
+//! scatters like its material (usually `isotropic`), and a `grid_medium`
+//! fills a box with a volume read from an NRRD voxel grid. Any object can be
+//! moved into place by a list of `transform` steps. For example:
+//!
+//! ```toml
+//! [camera]
+//! lookfrom = [13.0, 2.0, 3.0]
+//! lookat = [0.0, 0.0, 0.0]
+//! vfov = 20.0
+//! aperture = 0.1
+//! focus_dist = 10.0
+//!
+//! [textures.checker]
+//! type = "checker"
+//! odd = [0.2, 0.3, 0.1]
+//! even = [0.9, 0.9, 0.9]
+//! size = 10.0
+//!
+//! [materials.ground]
+//! type = "lambertian"
+//! texture = "checker"
+//!
+//! [[objects]]
+//! type = "sphere"
+//! center = [0.0, -1000.0, 0.0]
+//! radius = 1000.0
+//! material = "ground"
+//! ```
+
+use crate::aarect::{BoxShape, XYRect, XZRect, YZRect};
+use crate::constant_medium::ConstantMedium;
+use crate::environment::{Environment, EnvironmentMap, SkyGradient, SolidBackground, SunSky};
+use crate::grid_medium::{GridMedium, VoxelGrid};
+use crate::hit::{Hittable, HittableList};
+use crate::mat4::Mat4;
+use crate::material::Material;
+use crate::material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Metal};
+use crate::moving_sphere::MovingSphere;
+use crate::obj::load_obj;
+use crate::random::Rng;
+use crate::scene::{CameraSettings, Scene};
+use crate::sphere::Sphere;
+use crate::texture::{
+    CheckerTexture, ImageTexture, MarbleTexture, NoiseTexture, SolidColor, Texture, WoodTexture,
+};
+use crate::transform::Transform;
+use crate::triangle::{MeshData, Triangle, TriangleMesh};
+use crate::vec3::{Color, Point3, Vec3};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use toml::Spanned;
+
+/// An error encountered while loading a scene file.
+#[derive(Debug)]
+pub struct SceneError {
+    /// The line the error occurred on, if known.
+    line: Option<usize>,
+    message: String,
+}
+
+impl SceneError {
+    fn new(line: Option<usize>, message: String) -> Self {
+        SceneError { line, message }
+    }
+
+    /// Create an error located at the line containing the given spanned value.
+    fn at<T>(src: &str, span: &Spanned<T>, message: String) -> Self {
+        SceneError::new(Some(line_of(src, span.start())), message)
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<toml::de::Error> for SceneError {
+    fn from(e: toml::de::Error) -> Self {
+        // The toml error message already includes the line and column.
+        SceneError::new(None, e.to_string())
+    }
+}
+
+/// Convert a byte offset into a 1 based line number.
+fn line_of(src: &str, offset: usize) -> usize {
+    src[..offset].matches('\n').count() + 1
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    /// The color of rays that escape the scene, short for a solid
+    /// environment.
+    background: Option<Spanned<[f64; 3]>>,
+    environment: Option<EnvironmentDesc>,
+    camera: Spanned<CameraDesc>,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    lookfrom: [f64; 3],
+    lookat: [f64; 3],
+    vup: Option<[f64; 3]>,
+    vfov: f64,
+    #[serde(default)]
+    aperture: f64,
+    /// Defaults to the distance between lookfrom and lookat.
+    focus_dist: Option<f64>,
+    /// Shutter open and close times, the shutter is only open at time 0 by
+    /// default.
+    #[serde(default)]
+    time0: f64,
+    time1: Option<f64>,
+}
+
+/// An environment description. Which fields are used depends on the type.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    color: Option<[f64; 3]>,
+    horizon: Option<[f64; 3]>,
+    zenith: Option<[f64; 3]>,
+    /// Path to a .hdr or .pfm latitude-longitude map, relative to the scene
+    /// file.
+    path: Option<String>,
+    /// Rotation of the map about the y axis in degrees.
+    rotation: Option<f64>,
+    sun_direction: Option<[f64; 3]>,
+    turbidity: Option<f64>,
+    sun_intensity: Option<f64>,
+    /// Angular diameter of the sun in degrees.
+    sun_size: Option<f64>,
+    intensity: Option<f64>,
+}
+
+/// A texture description. Which fields are required depends on the type.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    color: Option<[f64; 3]>,
+    odd: Option<[f64; 3]>,
+    even: Option<[f64; 3]>,
+    size: Option<f64>,
+    /// Path to a PNG or PPM image, relative to the scene file.
+    path: Option<String>,
+    /// Features per unit of distance of a procedural texture.
+    scale: Option<f64>,
+    /// The colors of wood.
+    light: Option<[f64; 3]>,
+    dark: Option<[f64; 3]>,
+    /// Seed for the random noise, textures with the same seed match.
+    seed: Option<u64>,
+}
+
+/// A material description. Which fields are required depends on the type.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    albedo: Option<[f64; 3]>,
+    /// The name of a texture to use instead of a constant albedo.
+    texture: Option<Spanned<String>>,
+    /// The emitted color of a light.
+    emit: Option<[f64; 3]>,
+    fuzz: Option<f64>,
+    ref_idx: Option<f64>,
+}
+
+/// An object description. Which fields are required depends on the type.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    material: Option<Spanned<String>>,
+    center: Option<[f64; 3]>,
+    radius: Option<f64>,
+    /// The centers of a moving sphere at `time0` and `time1`, which default
+    /// to the camera's shutter times.
+    center0: Option<[f64; 3]>,
+    center1: Option<[f64; 3]>,
+    time0: Option<f64>,
+    time1: Option<f64>,
+    vertices: Option<Spanned<Vec<[f64; 3]>>>,
+    normals: Option<Spanned<Vec<[f64; 3]>>>,
+    triangles: Option<Spanned<Vec<[usize; 3]>>>,
+    /// Path to an OBJ file, or the NRRD file of a grid medium, relative to
+    /// the scene file.
+    path: Option<String>,
+    /// The OBJ groups to include, all groups are included if not set.
+    groups: Option<Vec<String>>,
+    /// Fills the object with a volume of this density instead.
+    density: Option<f64>,
+    /// Opposite corners of a rect, a box, or the box a grid medium fills.
+    min: Option<[f64; 3]>,
+    max: Option<[f64; 3]>,
+    /// Scales for the densities and emission read from a grid.
+    density_scale: Option<f64>,
+    emission_scale: Option<f64>,
+    /// Steps moving the object into place, applied in order.
+    transform: Option<Spanned<Vec<Spanned<TransformDesc>>>>,
+}
+
+/// A step of an object's transform, one of a `translate`, a `rotate` about an
+/// axis by `angle` degrees, or a `scale` along each axis.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDesc {
+    translate: Option<[f64; 3]>,
+    rotate: Option<[f64; 3]>,
+    angle: Option<f64>,
+    scale: Option<[f64; 3]>,
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn color(v: [f64; 3]) -> Color {
+    Color::new(v[0], v[1], v[2])
+}
+
+/// Returns the value of a field required by the type, or an error on the line
+/// of the type.
+fn required<T>(
+    src: &str,
+    kind: &Spanned<String>,
+    field: &str,
+    value: Option<T>,
+) -> Result<T, SceneError> {
+    value.ok_or_else(|| {
+        SceneError::at(
+            src,
+            kind,
+            format!("{} is missing required field '{}'", kind.get_ref(), field),
+        )
+    })
+}
+
+/// Returns the value of a field that must be finite and positive, or an error
+/// on the line of the type.
+fn positive(src: &str, kind: &Spanned<String>, field: &str, value: f64) -> Result<f64, SceneError> {
+    if value.is_finite() && value > 0.0 {
+        Ok(value)
+    } else {
+        Err(SceneError::at(
+            src,
+            kind,
+            format!("{} of a {} must be positive", field, kind.get_ref()),
+        ))
+    }
+}
+
+/// Returns a point or vector field, or an error on the line of `span` if any
+/// of its components aren't finite.
+fn finite<T>(
+    src: &str,
+    span: &Spanned<T>,
+    field: &str,
+    value: [f64; 3],
+) -> Result<Vec3, SceneError> {
+    if value.iter().all(|x| x.is_finite()) {
+        Ok(vec3(value))
+    } else {
+        Err(SceneError::at(
+            src,
+            span,
+            format!("{} must be finite", field),
+        ))
+    }
+}
+
+/// Returns the `min` and `max` corners of a box shaped object, or an error on
+/// the line of the type if they aren't ordered along every axis.
+fn box_corners(
+    src: &str,
+    kind: &Spanned<String>,
+    object: &ObjectDesc,
+) -> Result<(Point3, Point3), SceneError> {
+    let min: Point3 = finite(src, kind, "min", required(src, kind, "min", object.min)?)?;
+    let max: Point3 = finite(src, kind, "max", required(src, kind, "max", object.max)?)?;
+    if !(0..3).all(|axis| min[axis] < max[axis]) {
+        return Err(SceneError::at(
+            src,
+            kind,
+            format!(
+                "the min corner of a {} must be below its max corner along every axis",
+                kind.get_ref()
+            ),
+        ));
+    }
+    Ok((min, max))
+}
+
+/// Load a scene from a TOML file on disk.
+pub fn load_scene(path: &Path) -> Result<Scene, SceneError> {
+    let src = fs::read_to_string(path)
+        .map_err(|e| SceneError::new(None, format!("unable to read {}: {}", path.display(), e)))?;
+
+    parse_scene(&src, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// Parse a scene from the contents of a TOML scene file. Paths in the scene
+/// are relative to `base_dir`.
+pub fn parse_scene(src: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    let desc: SceneDesc = toml::from_str(src)?;
+
+    let camera_desc = desc.camera.get_ref();
+    let lookfrom = finite(src, &desc.camera, "lookfrom", camera_desc.lookfrom)?;
+    let lookat = finite(src, &desc.camera, "lookat", camera_desc.lookat)?;
+    let vup = finite(
+        src,
+        &desc.camera,
+        "vup",
+        camera_desc.vup.unwrap_or([0.0, 1.0, 0.0]),
+    )?;
+    let camera = CameraSettings {
+        lookfrom,
+        lookat,
+        vup,
+        vfov: camera_desc.vfov,
+        aperture: camera_desc.aperture,
+        focus_dist: camera_desc
+            .focus_dist
+            .unwrap_or_else(|| (lookfrom - lookat).length()),
+        time0: camera_desc.time0,
+        time1: camera_desc.time1.unwrap_or(camera_desc.time0),
+    };
+
+    // Moving spheres move while the camera's shutter is open, or from time 0
+    // to 1 if it isn't.
+    let shutter = if camera.time1 > camera.time0 {
+        (camera.time0, camera.time1)
+    } else {
+        (0.0, 1.0)
+    };
+
+    let mut textures: HashMap<&str, Arc<dyn Texture + Send + Sync>> = HashMap::new();
+    for (name, texture) in desc.textures.iter() {
+        textures.insert(name, build_texture(src, texture, base_dir)?);
+    }
+
+    let mut materials: HashMap<&str, Arc<dyn Material + Send + Sync>> = HashMap::new();
+    let mut light_materials = HashSet::new();
+    for (name, material) in desc.materials.iter() {
+        materials.insert(name, build_material(src, material, &textures)?);
+        if material.kind.get_ref() == "diffuse_light" {
+            light_materials.insert(name.as_str());
+        }
+    }
+
+    // Objects made of a light material are also added to the lights.
+    let mut loaded_meshes = HashMap::new();
+    let mut world = HittableList::default();
+    let mut lights = HittableList::default();
+    for object in desc.objects.iter() {
+        let kind = &object.kind;
+        let mut added = HittableList::default();
+        let lookup_material = |name: &Spanned<String>| match materials.get(name.get_ref().as_str())
+        {
+            Some(material) => Ok(material.clone()),
+            None => Err(SceneError::at(
+                src,
+                name,
+                format!("unknown material '{}'", name.get_ref()),
+            )),
+        };
+        let material = || -> Result<Arc<dyn Material + Send + Sync>, SceneError> {
+            lookup_material(required(src, kind, "material", object.material.as_ref())?)
+        };
+
+        match kind.get_ref().as_str() {
+            "sphere" => {
+                let material = material()?;
+                let center = required(src, kind, "center", object.center)?;
+                let center: Point3 = finite(src, kind, "center", center)?;
+                let radius = required(src, kind, "radius", object.radius)?;
+                let radius = positive(src, kind, "radius", radius)?;
+                added.add(Sphere::new_arc(center, radius, material));
+            }
+            "moving_sphere" => {
+                let material = material()?;
+                let center0 = required(src, kind, "center0", object.center0)?;
+                let center0: Point3 = finite(src, kind, "center0", center0)?;
+                let center1 = required(src, kind, "center1", object.center1)?;
+                let center1: Point3 = finite(src, kind, "center1", center1)?;
+                let radius = required(src, kind, "radius", object.radius)?;
+                let radius = positive(src, kind, "radius", radius)?;
+                added.add(MovingSphere::new_arc(
+                    center0,
+                    center1,
+                    object.time0.unwrap_or(shutter.0),
+                    object.time1.unwrap_or(shutter.1),
+                    radius,
+                    material,
+                ));
+            }
+            "triangle" => {
+                let material = material()?;
+                let vertices = required(src, kind, "vertices", object.vertices.as_ref())?;
+                match vertices.get_ref().as_slice() {
+                    [p0, p1, p2] => {
+                        let p0 = finite(src, vertices, "vertices", *p0)?;
+                        let p1 = finite(src, vertices, "vertices", *p1)?;
+                        let p2 = finite(src, vertices, "vertices", *p2)?;
+                        added.add(Triangle::new_arc(p0, p1, p2, material))
+                    }
+                    _ => {
+                        return Err(SceneError::at(
+                            src,
+                            vertices,
+                            "a triangle must have exactly 3 vertices".to_string(),
+                        ))
+                    }
+                }
+            }
+            "rect" => {
+                let material = material()?;
+                let min: Point3 =
+                    finite(src, kind, "min", required(src, kind, "min", object.min)?)?;
+                let max: Point3 =
+                    finite(src, kind, "max", required(src, kind, "max", object.max)?)?;
+                // The rectangle lies in the plane of the axis its corners
+                // share.
+                let flat: Vec<usize> = (0..3).filter(|&axis| min[axis] == max[axis]).collect();
+                let rect: Arc<dyn Hittable + Send + Sync> = match flat.as_slice() {
+                    [0] => Arc::new(YZRect::new(
+                        min.y(),
+                        max.y(),
+                        min.z(),
+                        max.z(),
+                        min.x(),
+                        material,
+                    )),
+                    [1] => Arc::new(XZRect::new(
+                        min.x(),
+                        max.x(),
+                        min.z(),
+                        max.z(),
+                        min.y(),
+                        material,
+                    )),
+                    [2] => Arc::new(XYRect::new(
+                        min.x(),
+                        max.x(),
+                        min.y(),
+                        max.y(),
+                        min.z(),
+                        material,
+                    )),
+                    _ => {
+                        return Err(SceneError::at(
+                            src,
+                            kind,
+                            "the corners of a rect must differ along exactly two axes".to_string(),
+                        ))
+                    }
+                };
+                added.add(rect);
+            }
+            "box" => {
+                let material = material()?;
+                let (min, max) = box_corners(src, kind, object)?;
+                added.add(BoxShape::new_arc(min, max, material));
+            }
+            "grid_medium" => {
+                let material = material()?;
+                let path = base_dir.join(required(src, kind, "path", object.path.as_ref())?);
+                let (min, max) = box_corners(src, kind, object)?;
+                let density_scale = positive(
+                    src,
+                    kind,
+                    "density_scale",
+                    object.density_scale.unwrap_or(1.0),
+                )?;
+                let grid = VoxelGrid::load(&path).map_err(|e| {
+                    SceneError::at(src, kind, format!("unable to load grid: {}", e))
+                })?;
+                added.add(GridMedium::new_arc(
+                    grid,
+                    min,
+                    max,
+                    density_scale,
+                    object.emission_scale.unwrap_or(1.0),
+                    material,
+                ));
+            }
+            "mesh" if object.path.is_some() => {
+                let path = base_dir.join(object.path.as_ref().unwrap());
+                let material_name = object.material.as_ref().map(|name| name.get_ref().clone());
+                let key = (path, material_name);
+
+                // Objects loading the same file with the same material share
+                // the meshes, so they can be instanced by transforms.
+                if !loaded_meshes.contains_key(&key) {
+                    let default_material = match &object.material {
+                        Some(name) => Some(lookup_material(name)?),
+                        None => None,
+                    };
+                    let groups = load_obj(&key.0, default_material).map_err(|e| {
+                        SceneError::at(src, kind, format!("unable to load mesh: {}", e))
+                    })?;
+                    if groups.is_empty() {
+                        return Err(SceneError::at(
+                            src,
+                            kind,
+                            format!("mesh {} has no faces", key.0.display()),
+                        ));
+                    }
+                    let meshes: Vec<(String, Arc<dyn Hittable + Send + Sync>)> = groups
+                        .into_iter()
+                        .map(|group| {
+                            let mesh: Arc<dyn Hittable + Send + Sync> = Arc::new(group.mesh);
+                            (group.name, mesh)
+                        })
+                        .collect();
+                    loaded_meshes.insert(key.clone(), meshes);
+                }
+
+                for (name, mesh) in loaded_meshes[&key].iter() {
+                    let included = match &object.groups {
+                        Some(names) => names.contains(name),
+                        None => true,
+                    };
+                    if included {
+                        added.add(mesh.clone());
+                    }
+                }
+            }
+            "mesh" => {
+                let material = material()?;
+                let vertices = required(src, kind, "vertices", object.vertices.as_ref())?;
+                let triangles = required(src, kind, "triangles", object.triangles.as_ref())?;
+                let positions = vertices
+                    .get_ref()
+                    .iter()
+                    .map(|v| finite(src, vertices, "vertices", *v))
+                    .collect::<Result<Vec<Point3>, _>>()?;
+
+                if triangles.get_ref().is_empty() {
+                    return Err(SceneError::at(
+                        src,
+                        triangles,
+                        "a mesh must have at least one triangle".to_string(),
+                    ));
+                }
+                if let Some(index) = triangles
+                    .get_ref()
+                    .iter()
+                    .flatten()
+                    .find(|&&i| i >= positions.len())
+                {
+                    return Err(SceneError::at(
+                        src,
+                        triangles,
+                        format!(
+                            "vertex index {} is out of range, the mesh has {} vertices",
+                            index,
+                            positions.len()
+                        ),
+                    ));
+                }
+
+                let normals = match &object.normals {
+                    Some(normals) if normals.get_ref().len() != positions.len() => {
+                        return Err(SceneError::at(
+                            src,
+                            normals,
+                            format!(
+                                "expected one normal per vertex, got {} normals for {} vertices",
+                                normals.get_ref().len(),
+                                positions.len()
+                            ),
+                        ))
+                    }
+                    Some(normals) => Some(
+                        normals
+                            .get_ref()
+                            .iter()
+                            .map(|n| finite(src, normals, "normals", *n))
+                            .collect::<Result<Vec<Vec3>, _>>()?,
+                    ),
+                    None => None,
+                };
+
+                let mesh = MeshData {
+                    positions,
+                    normals,
+                    uvs: None,
+                };
+                added.add(Arc::new(TriangleMesh::new(
+                    mesh,
+                    triangles.get_ref(),
+                    material,
+                )));
+            }
+            other => {
+                return Err(SceneError::at(
+                    src,
+                    kind,
+                    format!("unknown object type '{}'", other),
+                ))
+            }
+        }
+
+        if let Some(steps) = &object.transform {
+            let matrix = build_transform(src, steps)?;
+            let objects = added;
+            added = HittableList::default();
+            for object in objects.objects() {
+                added.add(Arc::new(Transform::new(object.clone(), matrix)));
+            }
+        }
+
+        // An object with a density is the boundary of a volume filled with
+        // its material.
+        if let Some(density) = object.density {
+            let density = positive(src, kind, "density", density)?;
+            let boundary: Arc<dyn Hittable + Send + Sync> = match added.objects() {
+                [object] => object.clone(),
+                _ => Arc::new(added),
+            };
+            added = HittableList::default();
+            added.add(ConstantMedium::new_arc(boundary, density, material()?));
+        }
+
+        // Moving spheres can't be sampled without knowing the time, and
+        // volumes can't be sampled at all, so they only light the scene when
+        // hit by chance.
+        let sampleable = !matches!(kind.get_ref().as_str(), "moving_sphere" | "grid_medium")
+            && object.density.is_none();
+        let is_light = match &object.material {
+            Some(name) if sampleable => light_materials.contains(name.get_ref().as_str()),
+            _ => false,
+        };
+        for added in added.objects() {
+            world.add(added.clone());
+            if is_light {
+                lights.add(added.clone());
+            }
+        }
+    }
+
+    let environment = match (&desc.background, &desc.environment) {
+        (Some(background), Some(_)) => {
+            return Err(SceneError::at(
+                src,
+                background,
+                "background and environment can't both be set".to_string(),
+            ))
+        }
+        (Some(background), None) => SolidBackground::new(color(*background.get_ref())),
+        (None, Some(environment)) => build_environment(src, environment, base_dir)?,
+        (None, None) => SkyGradient::book(),
+    };
+
+    Ok(Scene {
+        world,
+        camera,
+        environment,
+        lights,
+    })
+}
+
+fn build_environment(
+    src: &str,
+    environment: &EnvironmentDesc,
+    base_dir: &Path,
+) -> Result<Arc<dyn Environment + Send + Sync>, SceneError> {
+    let kind = &environment.kind;
+    let intensity = environment.intensity.unwrap_or(1.0);
+    Ok(match kind.get_ref().as_str() {
+        "solid" => SolidBackground::new(color(required(src, kind, "color", environment.color)?)),
+        "gradient" => SkyGradient::new(
+            environment.horizon.map_or(Color::new(1.0, 1.0, 1.0), color),
+            environment.zenith.map_or(Color::new(0.5, 0.7, 1.0), color),
+        ),
+        "map" => {
+            let path = base_dir.join(required(src, kind, "path", environment.path.as_ref())?);
+            EnvironmentMap::load(&path, intensity, environment.rotation.unwrap_or(0.0)).map_err(
+                |e| {
+                    SceneError::at(
+                        src,
+                        kind,
+                        format!("unable to load environment map {}: {}", path.display(), e),
+                    )
+                },
+            )?
+        }
+        "sun_sky" => {
+            let sun_direction = vec3(required(
+                src,
+                kind,
+                "sun_direction",
+                environment.sun_direction,
+            )?);
+            let length = sun_direction.length();
+            if !(length.is_finite() && length > 0.0) {
+                return Err(SceneError::at(
+                    src,
+                    kind,
+                    "sun_direction must be a finite, non-zero vector".to_string(),
+                ));
+            }
+            let sun_size = positive(src, kind, "sun_size", environment.sun_size.unwrap_or(0.53))?;
+            SunSky::new(
+                sun_direction,
+                environment.turbidity.unwrap_or(3.0),
+                intensity,
+                environment.sun_intensity.unwrap_or(1000.0),
+                sun_size,
+            )
+        }
+        other => {
+            return Err(SceneError::at(
+                src,
+                kind,
+                format!("unknown environment type '{}'", other),
+            ))
+        }
+    })
+}
+
+/// Combine the steps of a transform into a single matrix.
+fn build_transform(
+    src: &str,
+    steps: &Spanned<Vec<Spanned<TransformDesc>>>,
+) -> Result<Mat4, SceneError> {
+    let mut matrix = Mat4::identity();
+    for step in steps.get_ref() {
+        let desc = step.get_ref();
+        let error = |message: &str| Err(SceneError::at(src, step, message.to_string()));
+        let step_matrix = match (desc.translate, desc.rotate, desc.angle, desc.scale) {
+            (Some(offset), None, None, None) => {
+                Mat4::translation(finite(src, step, "translate", offset)?)
+            }
+            (None, Some(axis), Some(angle), None) => {
+                let axis = finite(src, step, "rotate", axis)?;
+                if axis.length_squared() == 0.0 {
+                    return error("the axis of a rotation can't be zero");
+                }
+                if !angle.is_finite() {
+                    return error("angle must be finite");
+                }
+                Mat4::rotation(axis, angle)
+            }
+            (None, Some(_), None, None) => return error("a rotation needs an angle"),
+            (None, None, None, Some(factors)) => {
+                Mat4::scaling(finite(src, step, "scale", factors)?)
+            }
+            _ => return error("each transform step must be one translate, rotate or scale"),
+        };
+        matrix = step_matrix * matrix;
+    }
+
+    if matrix.inverse().is_none() {
+        return Err(SceneError::at(
+            src,
+            steps,
+            "the transform can't be inverted".to_string(),
+        ));
+    }
+    Ok(matrix)
+}
+
+fn build_texture(
+    src: &str,
+    texture: &TextureDesc,
+    base_dir: &Path,
+) -> Result<Arc<dyn Texture + Send + Sync>, SceneError> {
+    let kind = &texture.kind;
+    let mut rng = Rng::new(texture.seed.unwrap_or(0));
+    Ok(match kind.get_ref().as_str() {
+        "solid" => SolidColor::new(color(required(src, kind, "color", texture.color)?)),
+        "checker" => CheckerTexture::from_colors(
+            color(required(src, kind, "odd", texture.odd)?),
+            color(required(src, kind, "even", texture.even)?),
+            positive(src, kind, "size", texture.size.unwrap_or(1.0))?,
+        ),
+        "noise" => NoiseTexture::new(
+            texture.color.map_or(Color::new(1.0, 1.0, 1.0), color),
+            texture.scale.unwrap_or(1.0),
+            &mut rng,
+        ),
+        "marble" => MarbleTexture::new(
+            texture.color.map_or(Color::new(1.0, 1.0, 1.0), color),
+            texture.scale.unwrap_or(1.0),
+            &mut rng,
+        ),
+        "wood" => WoodTexture::new(
+            texture.light.map_or(Color::new(0.75, 0.55, 0.33), color),
+            texture.dark.map_or(Color::new(0.4, 0.24, 0.1), color),
+            texture.scale.unwrap_or(10.0),
+            &mut rng,
+        ),
+        "image" => {
+            let path = base_dir.join(required(src, kind, "path", texture.path.as_ref())?);
+            ImageTexture::load(&path).map_err(|e| {
+                SceneError::at(
+                    src,
+                    kind,
+                    format!("unable to load image {}: {}", path.display(), e),
+                )
+            })?
+        }
+        other => {
+            return Err(SceneError::at(
+                src,
+                kind,
+                format!("unknown texture type '{}'", other),
+            ))
+        }
+    })
+}
+
+/// Returns the color of a material, either a named texture or the constant
+/// color of the given field.
+fn material_texture(
+    src: &str,
+    material: &MaterialDesc,
+    textures: &HashMap<&str, Arc<dyn Texture + Send + Sync>>,
+    field: &str,
+    value: Option<[f64; 3]>,
+) -> Result<Arc<dyn Texture + Send + Sync>, SceneError> {
+    match &material.texture {
+        Some(name) => match textures.get(name.get_ref().as_str()) {
+            Some(texture) => Ok(texture.clone()),
+            None => Err(SceneError::at(
+                src,
+                name,
+                format!("unknown texture '{}'", name.get_ref()),
+            )),
+        },
+        None => Ok(SolidColor::new(color(required(
+            src,
+            &material.kind,
+            field,
+            value,
+        )?))),
+    }
+}
+
+fn build_material(
+    src: &str,
+    material: &MaterialDesc,
+    textures: &HashMap<&str, Arc<dyn Texture + Send + Sync>>,
+) -> Result<Arc<dyn Material + Send + Sync>, SceneError> {
+    let kind = &material.kind;
+    let texture = |field, value| material_texture(src, material, textures, field, value);
+
+    Ok(match kind.get_ref().as_str() {
+        "lambertian" => Lambertian::with_texture(texture("albedo", material.albedo)?),
+        "metal" => {
+            let fuzz = material.fuzz.unwrap_or(0.0);
+            if !(fuzz.is_finite() && fuzz >= 0.0) {
+                return Err(SceneError::at(
+                    src,
+                    kind,
+                    "fuzz of a metal must not be negative".to_string(),
+                ));
+            }
+            Metal::with_texture(texture("albedo", material.albedo)?, fuzz)
+        }
+        "diffuse_light" => DiffuseLight::with_texture(texture("emit", material.emit)?),
+        "dielectric" => {
+            let ref_idx = required(src, kind, "ref_idx", material.ref_idx)?;
+            Dielectric::new(positive(src, kind, "ref_idx", ref_idx)?)
+        }
+        "isotropic" => Isotropic::with_texture(texture("albedo", material.albedo)?),
+        other => {
+            return Err(SceneError::at(
+                src,
+                kind,
+                format!("unknown material type '{}'", other),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scene_file::parse_scene;
+    use crate::vec3::Point3;
+    use std::path::Path;
+
+    const CAMERA: &str =
+        "[camera]\nlookfrom = [0.0, 0.0, 1.0]\nlookat = [0.0, 0.0, 0.0]\nvfov = 90.0\n";
+
+    #[test]
+    fn parse_valid_scene() {
+        let src = format!(
+            "{}\n[materials.red]\ntype = \"lambertian\"\nalbedo = [1.0, 0.0, 0.0]\n\n\
+             [[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -1.0]\nradius = 0.5\nmaterial = \"red\"\n",
+            CAMERA
+        );
+
+        let scene = parse_scene(&src, Path::new("")).unwrap();
+        assert_eq!(scene.world.objects().len(), 1);
+        assert_eq!(scene.camera.focus_dist, 1.0);
+        assert!(scene.lights.objects().is_empty());
+    }
+
+    #[test]
+    fn background_and_environment_conflict() {
+        let src = format!(
+            "background = [0.0, 0.0, 0.0]\n{}\n[environment]\ntype = \"gradient\"\n",
+            CAMERA
+        );
+
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 1: background and environment can't both be set"
+        );
+    }
+
+    #[test]
+    fn invalid_sun() {
+        let src = format!(
+            "{}\n[environment]\ntype = \"sun_sky\"\nsun_direction = [0.0, 0.0, 0.0]\n",
+            CAMERA
+        );
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 7: sun_direction must be a finite, non-zero vector"
+        );
+
+        let src = format!(
+            "{}\n[environment]\ntype = \"sun_sky\"\nsun_direction = [0.0, 1.0, 0.0]\nsun_size = 0.0\n",
+            CAMERA
+        );
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 7: sun_size of a sun_sky must be positive"
+        );
+    }
+
+    #[test]
+    fn unknown_material_reports_line() {
+        let src = format!(
+            "{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -1.0]\nradius = 0.5\nmaterial = \"blue\"\n",
+            CAMERA
+        );
+
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(error.to_string(), "line 10: unknown material 'blue'");
+    }
+
+    #[test]
+    fn malformed_value_reports_line() {
+        let src = format!(
+            "{}\n[materials.red]\ntype = \"metal\"\nalbedo = [1.0, 0.0]\n",
+            CAMERA
+        );
+
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert!(error.to_string().contains("at line 6"), "{}", error);
+    }
+
+    #[test]
+    fn textured_materials() {
+        let src = format!(
+            "{}\n[textures.checker]\ntype = \"checker\"\nodd = [0.0, 0.0, 0.0]\neven = [1.0, 1.0, 1.0]\n\n\
+             [materials.ground]\ntype = \"lambertian\"\ntexture = \"checker\"\n\n\
+             [materials.floor]\ntype = \"metal\"\ntexture = \"tiles\"\n",
+            CAMERA
+        );
+
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(error.to_string(), "line 17: unknown texture 'tiles'");
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let src = format!(
+            "{}\n[materials.lamp]\ntype = \"diffuse_light\"\nemit = [1.0, 1.0, 1.0]\n\n\
+             [[objects]]\ntype = \"mesh\"\nvertices = [[0.0, 0.0, 0.0]]\ntriangles = []\nmaterial = \"lamp\"\n",
+            CAMERA
+        );
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 13: a mesh must have at least one triangle"
+        );
+    }
+
+    #[test]
+    fn invalid_density() {
+        for density in ["0.0", "-1.0", "nan", "inf"].iter() {
+            let src = format!(
+                "{}\n[materials.fog]\ntype = \"isotropic\"\nalbedo = [1.0, 1.0, 1.0]\n\n\
+                 [[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -1.0]\nradius = 0.5\n\
+                 material = \"fog\"\ndensity = {}\n",
+                CAMERA, density
+            );
+            let error = parse_scene(&src, Path::new("")).err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                "line 11: density of a sphere must be positive"
+            );
+        }
+    }
+
+    #[test]
+    fn flat_box() {
+        let src = format!(
+            "{}\n[materials.red]\ntype = \"lambertian\"\nalbedo = [1.0, 0.0, 0.0]\n\n\
+             [[objects]]\ntype = \"box\"\nmin = [0.0, 0.0, 0.0]\nmax = [1.0, 1.0, 0.0]\nmaterial = \"red\"\n",
+            CAMERA
+        );
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 11: the min corner of a box must be below its max corner along every axis"
+        );
+    }
+
+    #[test]
+    fn invalid_grid_medium() {
+        let grid = "[materials.fog]\ntype = \"isotropic\"\nalbedo = [1.0, 1.0, 1.0]\n\n\
+                    [[objects]]\ntype = \"grid_medium\"\npath = \"smoke.nrrd\"\nmaterial = \"fog\"\n";
+        let src = format!(
+            "{}\n{}min = [0.0, 0.0, 0.0]\nmax = [1.0, 0.0, 1.0]\n",
+            CAMERA, grid
+        );
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 11: the min corner of a grid_medium must be below its max corner along every axis"
+        );
+
+        let src = format!(
+            "{}\n{}min = [0.0, 0.0, 0.0]\nmax = [1.0, 1.0, 1.0]\ndensity_scale = -1.0\n",
+            CAMERA, grid
+        );
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 11: density_scale of a grid_medium must be positive"
+        );
+    }
+
+    #[test]
+    fn non_finite_positions() {
+        let red = "[materials.red]\ntype = \"lambertian\"\nalbedo = [1.0, 0.0, 0.0]\n\n";
+        let cases = [
+            (
+                "type = \"sphere\"\ncenter = [nan, 0.0, 0.0]\nradius = 0.5\n",
+                "line 11: center must be finite",
+            ),
+            (
+                "type = \"moving_sphere\"\ncenter0 = [0.0, 0.0, 0.0]\ncenter1 = [inf, 0.0, 0.0]\nradius = 0.5\n",
+                "line 11: center1 must be finite",
+            ),
+            (
+                "type = \"triangle\"\nvertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, nan, 0.0]]\n",
+                "line 12: vertices must be finite",
+            ),
+            (
+                "type = \"rect\"\nmin = [0.0, 0.0, 0.0]\nmax = [1.0, inf, 0.0]\n",
+                "line 11: max must be finite",
+            ),
+            (
+                "type = \"box\"\nmin = [-inf, 0.0, 0.0]\nmax = [1.0, 1.0, 1.0]\n",
+                "line 11: min must be finite",
+            ),
+            (
+                "type = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nradius = 0.5\n\
+                 transform = [{ translate = [0.0, nan, 0.0] }]\n",
+                "line 14: translate must be finite",
+            ),
+        ];
+        for (object, message) in cases.iter() {
+            let src = format!(
+                "{}\n{}[[objects]]\n{}material = \"red\"\n",
+                CAMERA, red, object
+            );
+            let error = parse_scene(&src, Path::new("")).err().unwrap();
+            assert_eq!(error.to_string(), *message);
+        }
+
+        let src = "[camera]\nlookfrom = [0.0, 0.0, 1.0]\nlookat = [0.0, nan, 0.0]\nvfov = 90.0\n";
+        let error = parse_scene(src, Path::new("")).err().unwrap();
+        assert_eq!(error.to_string(), "line 1: lookat must be finite");
+    }
+
+    #[test]
+    fn invalid_material_parameters() {
+        let cases = [
+            (
+                "type = \"dielectric\"\nref_idx = 0.0\n",
+                "line 7: ref_idx of a dielectric must be positive",
+            ),
+            (
+                "type = \"dielectric\"\nref_idx = nan\n",
+                "line 7: ref_idx of a dielectric must be positive",
+            ),
+            (
+                "type = \"metal\"\nalbedo = [1.0, 1.0, 1.0]\nfuzz = -0.5\n",
+                "line 7: fuzz of a metal must not be negative",
+            ),
+        ];
+        for (material, message) in cases.iter() {
+            let src = format!("{}\n[materials.test]\n{}", CAMERA, material);
+            let error = parse_scene(&src, Path::new("")).err().unwrap();
+            assert_eq!(error.to_string(), *message);
+        }
+    }
+
+    #[test]
+    fn invalid_radius_and_size() {
+        for radius in ["0.0", "-0.5", "nan"].iter() {
+            let src = format!(
+                "{}\n[materials.red]\ntype = \"lambertian\"\nalbedo = [1.0, 0.0, 0.0]\n\n\
+                 [[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -1.0]\nradius = {}\nmaterial = \"red\"\n",
+                CAMERA, radius
+            );
+            let error = parse_scene(&src, Path::new("")).err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                "line 11: radius of a sphere must be positive"
+            );
+        }
+
+        let src = format!(
+            "{}\n[textures.checker]\ntype = \"checker\"\nodd = [0.0, 0.0, 0.0]\neven = [1.0, 1.0, 1.0]\nsize = -1.0\n",
+            CAMERA
+        );
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 7: size of a checker must be positive"
+        );
+    }
+
+    #[test]
+    fn transformed_objects() {
+        let object = "[materials.red]\ntype = \"lambertian\"\nalbedo = [1.0, 0.0, 0.0]\n\n\
+                      [[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nradius = 0.5\nmaterial = \"red\"\n";
+        let src = format!(
+            "{}\n{}transform = [\n  {{ scale = [1.0, 2.0, 1.0] }},\n  {{ translate = [0.0, 0.0, -2.0] }},\n]\n",
+            CAMERA, object
+        );
+        let scene = parse_scene(&src, Path::new("")).unwrap();
+        let bbox = scene.world.objects()[0].aabb().unwrap();
+        assert_eq!(bbox.min(), Point3::new(-0.5, -1.0, -2.5));
+
+        let src = format!(
+            "{}\n{}transform = [\n  {{ scale = [1.0, 2.0, 1.0] }},\n  {{ rotate = [0.0, 1.0, 0.0] }},\n]\n",
+            CAMERA, object
+        );
+        let error = parse_scene(&src, Path::new("")).err().unwrap();
+        assert_eq!(error.to_string(), "line 17: a rotation needs an angle");
+    }
+}
@@ -1,7 +1,11 @@
+use crate::aabb::Aabb;
 use crate::hit::HitRecord;
 use crate::hit::Hittable;
 use crate::material::Material;
+use crate::pdf::{ConePdf, Pdf, SpherePdf};
+use crate::random::Rng;
 use crate::ray::Ray;
+use crate::utility;
 use crate::vec3::{Point3, Vec3};
 use std::sync::Arc;
 
@@ -27,10 +31,43 @@ impl Sphere {
     ) -> Arc<Self> {
         Arc::new(Sphere::new(center, radius, material))
     }
+
+    /// Returns the u, v surface coordinates of a point on the unit sphere,
+    /// with u going around the y axis from x = -1 and v going from y = -1 to
+    /// y = 1.
+    pub fn get_sphere_uv(p: &Point3) -> (f64, f64) {
+        let theta = f64::acos(-p.y());
+        let phi = f64::atan2(-p.z(), p.x()) + utility::PI;
+
+        (phi / (2.0 * utility::PI), theta / utility::PI)
+    }
+
+    /// Returns the cone of directions covered by the sphere as seen from a
+    /// point outside it, or None if the point is inside.
+    fn cone_from(&self, origin: &Point3) -> Option<ConePdf> {
+        let towards_center = self.center - *origin;
+        let distance_squared = towards_center.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return None;
+        }
+
+        let cos_theta_max = f64::sqrt(1.0 - radius_squared / distance_squared);
+        Some(ConePdf::new(&towards_center, cos_theta_max))
+    }
+
+    fn hit_record(&self, r: &Ray, t: f64) -> HitRecord {
+        let p = r.at(t);
+        let outward_normal = (p - self.center) / self.radius;
+        // Hollow spheres have a negative radius, so their texture is mapped
+        // from the actual direction of the point.
+        let (u, v) = Sphere::get_sphere_uv(&((p - self.center) / self.radius.abs()));
+        HitRecord::new(p, r, outward_normal, t, u, v, self.material.clone())
+    }
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut Rng) -> Option<HitRecord> {
         let oc = r.origin() - self.center;
         let a = r.direction().length_squared();
         let half_b = Vec3::dot(&oc, &r.direction());
@@ -41,33 +78,52 @@ impl Hittable for Sphere {
             let root = discriminant.sqrt();
             let temp = (-half_b - root) / a;
             if temp < t_max && temp > t_min {
-                let t = temp;
-                let p = r.at(t);
-                let outward_normal = (p - self.center) / self.radius;
-                return Some(HitRecord::new(
-                    p,
-                    r,
-                    outward_normal,
-                    t,
-                    self.material.clone(),
-                ));
+                return Some(self.hit_record(r, temp));
             }
 
             let temp = (-half_b + root) / a;
             if temp < t_max && temp > t_min {
-                let t = temp;
-                let p = r.at(t);
-                let outward_normal = (p - self.center) / self.radius;
-                return Some(HitRecord::new(
-                    p,
-                    r,
-                    outward_normal,
-                    t,
-                    self.material.clone(),
-                ));
+                return Some(self.hit_record(r, temp));
             }
         }
 
         None
     }
+
+    fn aabb(&self) -> Option<Aabb> {
+        let radius = Vec3::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
+        Some(Aabb::new(self.center - radius, self.center + radius))
+    }
+
+    /// Points are chosen uniformly over the cone of directions the sphere
+    /// covers, or over all directions from inside it.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.cone_from(origin) {
+            Some(cone) => cone.value(direction),
+            None => SpherePdf.value(direction),
+        }
+    }
+
+    fn random_point(&self, origin: &Point3, rng: &mut Rng) -> Option<Point3> {
+        let cone = self.cone_from(origin);
+        let inside = cone.is_none();
+        let direction = match cone {
+            Some(cone) => Vec3::unit_vector(cone.generate(rng)),
+            None => SpherePdf.generate(rng),
+        };
+
+        let oc = *origin - self.center;
+        let radius_squared = self.radius * self.radius;
+
+        // Where the direction meets the sphere, the far side from inside.
+        let half_b = Vec3::dot(&oc, &direction);
+        let c = oc.length_squared() - radius_squared;
+        let root = f64::sqrt(f64::max(half_b * half_b - c, 0.0));
+        let t = if inside {
+            -half_b + root
+        } else {
+            -half_b - root
+        };
+        Some(*origin + t * direction)
+    }
 }
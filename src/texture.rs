@@ -0,0 +1,383 @@
+use crate::hdr;
+use crate::output::Image;
+use crate::perlin::Perlin;
+use crate::random::Rng;
+use crate::utility;
+use crate::vec3::{Color, Point3};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+pub trait Texture {
+    /// Returns the color of the texture at the surface coordinates u, v and
+    /// the point p.
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+impl std::fmt::Debug for dyn Texture + Send + Sync {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "dyn Texture")
+    }
+}
+
+/// A texture that is the same color everywhere.
+#[derive(Debug)]
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> Arc<Self> {
+        Arc::new(SolidColor { color })
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.color
+    }
+}
+
+/// A 3D checkerboard alternating between two textures, with squares of the
+/// given size.
+#[derive(Debug)]
+pub struct CheckerTexture {
+    odd: Arc<dyn Texture + Send + Sync>,
+    even: Arc<dyn Texture + Send + Sync>,
+    size: f64,
+}
+
+impl CheckerTexture {
+    pub fn new(
+        odd: Arc<dyn Texture + Send + Sync>,
+        even: Arc<dyn Texture + Send + Sync>,
+        size: f64,
+    ) -> Arc<Self> {
+        Arc::new(CheckerTexture { odd, even, size })
+    }
+
+    pub fn from_colors(odd: Color, even: Color, size: f64) -> Arc<Self> {
+        CheckerTexture::new(SolidColor::new(odd), SolidColor::new(even), size)
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let cell = |x: f64| (x / self.size).floor() as i64;
+        if (cell(p.x()) + cell(p.y()) + cell(p.z())) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// Perlin noise scaled to [0, 1] and tinted by a color. The scale sets how
+/// many features there are per unit of distance.
+#[derive(Debug)]
+pub struct NoiseTexture {
+    noise: Perlin,
+    color: Color,
+    scale: f64,
+}
+
+impl NoiseTexture {
+    pub fn new(color: Color, scale: f64, rng: &mut Rng) -> Arc<Self> {
+        Arc::new(NoiseTexture {
+            noise: Perlin::new(rng),
+            color,
+            scale,
+        })
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        0.5 * (1.0 + self.noise.noise(&(self.scale * *p))) * self.color
+    }
+}
+
+/// Marble-like veins, bands along the z axis disturbed by turbulence.
+#[derive(Debug)]
+pub struct MarbleTexture {
+    noise: Perlin,
+    color: Color,
+    scale: f64,
+}
+
+impl MarbleTexture {
+    pub fn new(color: Color, scale: f64, rng: &mut Rng) -> Arc<Self> {
+        Arc::new(MarbleTexture {
+            noise: Perlin::new(rng),
+            color,
+            scale,
+        })
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let phase = self.scale * p.z() + 10.0 * self.noise.turbulence(p, 7);
+        0.5 * (1.0 + phase.sin()) * self.color
+    }
+}
+
+/// Wood grain, rings around the y axis blending from the light to the dark
+/// color, wobbled by noise. The scale sets how many rings there are per unit
+/// of distance.
+#[derive(Debug)]
+pub struct WoodTexture {
+    noise: Perlin,
+    light: Color,
+    dark: Color,
+    scale: f64,
+}
+
+impl WoodTexture {
+    pub fn new(light: Color, dark: Color, scale: f64, rng: &mut Rng) -> Arc<Self> {
+        Arc::new(WoodTexture {
+            noise: Perlin::new(rng),
+            light,
+            dark,
+            scale,
+        })
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let radius = f64::sqrt(p.x() * p.x() + p.z() * p.z());
+        let rings = self.scale * radius + 2.0 * self.noise.noise(&(4.0 * *p));
+        // Each ring fades from light to dark and then sharply back.
+        let t = utility::clamp(rings - rings.floor(), 0.0, 1.0);
+        let t = t * t;
+        (1.0 - t) * self.light + t * self.dark
+    }
+}
+
+/// A texture mapped from an image using the surface coordinates, with (0, 0)
+/// at the bottom left of the image.
+#[derive(Debug)]
+pub struct ImageTexture {
+    image: Image,
+}
+
+impl ImageTexture {
+    pub fn new(image: Image) -> Arc<Self> {
+        Arc::new(ImageTexture { image })
+    }
+
+    /// Load an image texture from a PNG or PPM file.
+    pub fn load(path: &Path) -> io::Result<Arc<Self>> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let reader = BufReader::new(File::open(path)?);
+
+        let image = match extension.as_deref() {
+            Some("png") => read_png(reader)?,
+            Some("ppm") => read_ppm(reader)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "image textures must be .png or .ppm files",
+                ))
+            }
+        };
+
+        Ok(ImageTexture::new(image))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        let width = self.image.width();
+        let height = self.image.height();
+
+        // Clamp input texture coordinates to [0,1] x [1,0], flipping v to
+        // image coordinates.
+        let u = utility::clamp(u, 0.0, 1.0);
+        let v = 1.0 - utility::clamp(v, 0.0, 1.0);
+
+        let i = usize::min((u * width as f64) as usize, width - 1);
+        let j = usize::min((v * height as f64) as usize, height - 1);
+
+        self.image.pixels()[j * width + i]
+    }
+}
+
+/// Convert an 8-bit sRGB encoded value to linear, the inverse of the encoding
+/// used when writing images.
+fn byte_to_linear(value: u8) -> f64 {
+    utility::srgb_to_linear(value as f64 / 255.0)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Read an 8 or 16-bit PNG into linear colors.
+fn read_png(reader: impl Read) -> io::Result<Image> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let (info, mut png_reader) = decoder.read_info()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    png_reader.next_frame(&mut buffer)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        other => {
+            return Err(invalid_data(format!(
+                "unsupported PNG color type {:?}",
+                other
+            )))
+        }
+    };
+
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let pixels = buffer
+        .chunks(info.line_size)
+        .take(height)
+        .flat_map(|row| row[..width * channels].chunks(channels))
+        .map(|pixel| {
+            if channels < 3 {
+                let gray = byte_to_linear(pixel[0]);
+                Color::new(gray, gray, gray)
+            } else {
+                Color::new(
+                    byte_to_linear(pixel[0]),
+                    byte_to_linear(pixel[1]),
+                    byte_to_linear(pixel[2]),
+                )
+            }
+        })
+        .collect();
+
+    Ok(Image::new(width, height, pixels))
+}
+
+/// Read the next whitespace separated header token of a PPM file, skipping
+/// comments.
+fn next_ppm_token<'a>(data: &'a [u8], position: &mut usize) -> io::Result<&'a str> {
+    loop {
+        while *position < data.len() && data[*position].is_ascii_whitespace() {
+            *position += 1;
+        }
+        if *position < data.len() && data[*position] == b'#' {
+            while *position < data.len() && data[*position] != b'\n' {
+                *position += 1;
+            }
+            continue;
+        }
+        break;
+    }
+
+    let start = *position;
+    while *position < data.len() && !data[*position].is_ascii_whitespace() {
+        *position += 1;
+    }
+    if start == *position {
+        return Err(invalid_data("unexpected end of PPM file".to_string()));
+    }
+
+    std::str::from_utf8(&data[start..*position])
+        .map_err(|_| invalid_data("PPM header is not valid text".to_string()))
+}
+
+fn next_ppm_number(data: &[u8], position: &mut usize) -> io::Result<usize> {
+    let token = next_ppm_token(data, position)?;
+    token
+        .parse::<usize>()
+        .map_err(|_| invalid_data(format!("'{}' is not a valid PPM value", token)))
+}
+
+/// Read an ASCII (P3) or binary (P6) PPM into linear colors.
+fn read_ppm(mut reader: impl Read) -> io::Result<Image> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let mut position = 0;
+    let magic = next_ppm_token(&data, &mut position)?.to_string();
+    let width = next_ppm_number(&data, &mut position)?;
+    let height = next_ppm_number(&data, &mut position)?;
+    let max_value = next_ppm_number(&data, &mut position)?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid_data(format!("invalid PPM max value {}", max_value)));
+    }
+
+    let count = hdr::checked_pixel_count(width, height)? * 3;
+    let values: Vec<usize> = match magic.as_str() {
+        "P3" => (0..count)
+            .map(|_| next_ppm_number(&data, &mut position))
+            .collect::<io::Result<_>>()?,
+        "P6" => {
+            // A single whitespace character separates the header from the data.
+            let start = position + 1;
+            let bytes_per_value = if max_value < 256 { 1 } else { 2 };
+            let end = start + count * bytes_per_value;
+            if end > data.len() {
+                return Err(invalid_data("unexpected end of PPM file".to_string()));
+            }
+
+            data[start..end]
+                .chunks(bytes_per_value)
+                .map(|b| match b {
+                    [value] => *value as usize,
+                    _ => (b[0] as usize) << 8 | b[1] as usize,
+                })
+                .collect()
+        }
+        _ => return Err(invalid_data(format!("unsupported PPM type '{}'", magic))),
+    };
+
+    let linear =
+        |value: usize| utility::srgb_to_linear(value.min(max_value) as f64 / max_value as f64);
+    let pixels = values
+        .chunks(3)
+        .map(|rgb| Color::new(linear(rgb[0]), linear(rgb[1]), linear(rgb[2])))
+        .collect();
+
+    Ok(Image::new(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::texture::{read_ppm, CheckerTexture, Texture};
+    use crate::vec3::{Color, Point3};
+
+    #[test]
+    fn checker_alternates() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let checker = CheckerTexture::from_colors(black, white, 1.0);
+
+        let at = |x, y, z| *checker.value(0.0, 0.0, &Point3::new(x, y, z));
+        assert_eq!(at(0.5, 0.5, 0.5), *white);
+        assert_eq!(at(1.5, 0.5, 0.5), *black);
+        assert_eq!(at(-0.5, 0.5, 0.5), *black);
+        assert_eq!(at(1.5, 1.5, 0.5), *white);
+    }
+
+    #[test]
+    fn read_ascii_and_binary_ppm() {
+        let ascii = b"P3\n# comment\n2 1\n255\n255 0 0 0 51 255\n";
+        let binary = b"P6\n2 1\n255\n\xff\x00\x00\x00\x33\xff";
+
+        for data in [&ascii[..], &binary[..]].iter() {
+            let image = read_ppm(*data).unwrap();
+            assert_eq!((image.width(), image.height()), (2, 1));
+            assert_eq!(*image.pixels()[0], *Color::new(1.0, 0.0, 0.0));
+            assert!((image.pixels()[1].y() - 0.033105).abs() < 1e-6);
+        }
+
+        assert!(read_ppm(&b"P3\n0 0\n255\n"[..]).is_err());
+        assert!(read_ppm(&b"P6\n99999999999 99999999999\n255\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::str::FromStr;
+
+/// A rectangle of pixels, from (x0, y0) up to but not including (x1, y1),
+/// with y counting rows down from the top of the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Region {
+    pub fn new(x0: usize, y0: usize, x1: usize, y1: usize) -> Self {
+        Region { x0, y0, x1, y1 }
+    }
+
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+
+    pub fn contains(&self, other: &Region) -> bool {
+        other.x0 >= self.x0 && other.y0 >= self.y0 && other.x1 <= self.x1 && other.y1 <= self.y1
+    }
+
+    /// The indices of the region's pixels in an image `image_width` wide,
+    /// stored row by row from the top left.
+    pub fn pixel_indices(&self, image_width: usize) -> impl Iterator<Item = usize> {
+        let region = *self;
+        (region.y0..region.y1)
+            .flat_map(move |y| (region.x0..region.x1).map(move |x| y * image_width + x))
+    }
+}
+
+/// Parse a region in the form "x0,y0,x1,y1".
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s
+            .split(',')
+            .map(|part| part.trim().parse::<usize>())
+            .collect();
+
+        match parts.as_slice() {
+            [Ok(x0), Ok(y0), Ok(x1), Ok(y1)] if x0 < x1 && y0 < y1 => {
+                Ok(Region::new(*x0, *y0, *x1, *y1))
+            }
+            _ => Err(format!(
+                "'{}' is not a region, expected x0,y0,x1,y1 with x0 < x1 and y0 < y1",
+                s
+            )),
+        }
+    }
+}
+
+/// The order tiles are rendered in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileOrder {
+    /// Row by row from the top left.
+    Scanline,
+    /// Outwards from the center, where the subject of the image usually is.
+    Spiral,
+    /// Along a Hilbert curve, so that tiles rendered one after another are
+    /// next to each other.
+    Hilbert,
+}
+
+impl TileOrder {
+    pub const NAMES: &'static [&'static str] = &["scanline", "spiral", "hilbert"];
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!(
+                "unknown tile order '{}', expected one of: {}",
+                s,
+                TileOrder::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Split a region into square tiles of `tile_size` pixels, in the given
+/// order. The tiles at the right and bottom edges may be smaller.
+pub fn tiles(region: Region, tile_size: usize, order: TileOrder) -> Vec<Region> {
+    let columns = region.width().div_ceil(tile_size);
+    let rows = region.height().div_ceil(tile_size);
+
+    let positions = match order {
+        TileOrder::Scanline => (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect(),
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => {
+            let size = usize::max(columns, rows).next_power_of_two();
+            let mut positions: Vec<_> = (0..rows)
+                .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                .collect();
+            positions.sort_by_key(|&(column, row)| hilbert_index(size, column, row));
+            positions
+        }
+    };
+
+    positions
+        .into_iter()
+        .map(|(column, row)| {
+            let x0 = region.x0 + column * tile_size;
+            let y0 = region.y0 + row * tile_size;
+            Region::new(
+                x0,
+                y0,
+                usize::min(x0 + tile_size, region.x1),
+                usize::min(y0 + tile_size, region.y1),
+            )
+        })
+        .collect()
+}
+
+/// The positions in a grid of tiles, walking around in a growing square from
+/// the center.
+fn spiral(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let mut positions = Vec::with_capacity(columns * rows);
+    let (mut x, mut y) = (((columns - 1) / 2) as i64, ((rows - 1) / 2) as i64);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut step = 0;
+
+    while positions.len() < columns * rows {
+        // Each side of the square is one longer than the side two before.
+        let (dx, dy) = directions[step % 4];
+        for _ in 0..step / 2 + 1 {
+            if x >= 0 && y >= 0 && (x as usize) < columns && (y as usize) < rows {
+                positions.push((x as usize, y as usize));
+            }
+            x += dx;
+            y += dy;
+        }
+        step += 1;
+    }
+
+    positions
+}
+
+/// The distance along a Hilbert curve filling a `size` by `size` grid, where
+/// `size` is a power of two, of the point at (x, y).
+fn hilbert_index(size: usize, mut x: usize, mut y: usize) -> usize {
+    let mut index = 0;
+    let mut s = size / 2;
+
+    while s > 0 {
+        let rx = (x & s > 0) as usize;
+        let ry = (y & s > 0) as usize;
+        index += s * s * ((3 * rx) ^ ry);
+
+        // Rotate the quadrant so the curve inside it lines up.
+        if ry == 0 {
+            if rx == 1 {
+                x = size - 1 - x;
+                y = size - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tile::{tiles, Region, TileOrder};
+
+    #[test]
+    fn tiles_cover_the_region_once() {
+        let region = Region::new(5, 3, 105, 73);
+        for &order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert].iter() {
+            let tiles = tiles(region, 16, order);
+            assert_eq!(tiles.len(), 7 * 5);
+
+            let mut covered = vec![0; 120 * 80];
+            for tile in tiles.iter() {
+                assert!(region.contains(tile));
+                for index in tile.pixel_indices(120) {
+                    covered[index] += 1;
+                }
+            }
+            for (index, count) in covered.iter().enumerate() {
+                let pixel = Region::new(index % 120, index / 120, index % 120 + 1, index / 120 + 1);
+                assert_eq!(*count, region.contains(&pixel) as i32);
+            }
+        }
+
+        let spiral = tiles(region, 16, TileOrder::Spiral);
+        assert_eq!(spiral[0], Region::new(53, 35, 69, 51));
+    }
+
+    #[test]
+    fn hilbert_tiles_are_neighbours() {
+        let tiles = tiles(Region::new(0, 0, 64, 64), 8, TileOrder::Hilbert);
+        assert_eq!(tiles[0], Region::new(0, 0, 8, 8));
+        for pair in tiles.windows(2) {
+            let distance = (pair[0].x0 as i64 - pair[1].x0 as i64).abs()
+                + (pair[0].y0 as i64 - pair[1].y0 as i64).abs();
+            assert_eq!(distance, 8);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::mat4::Mat4;
+use crate::random::Rng;
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// An object placed in the world by an affine transform, so that one object
+/// can be shared by many instances.
+///
+/// Rays are moved into the object's space to be intersected, and the hits
+/// moved back out.
+pub struct Transform {
+    object: Arc<dyn Hittable + Send + Sync>,
+    /// From object space to world space.
+    matrix: Mat4,
+    /// From world space to object space.
+    inverse: Mat4,
+}
+
+impl Transform {
+    /// Place an object by the matrix, which must be invertible.
+    pub fn new(object: Arc<dyn Hittable + Send + Sync>, matrix: Mat4) -> Self {
+        let inverse = matrix.inverse().expect("transform must be invertible");
+        Transform {
+            object,
+            matrix,
+            inverse,
+        }
+    }
+}
+
+/// Builders for placing an object step by step, used by the tests. Scenes
+/// combine their steps into one matrix for `Transform::new` instead.
+#[cfg(test)]
+impl Transform {
+    /// Place an object without moving it, to build on with `translate`,
+    /// `rotate` and `scale`.
+    pub fn identity(object: Arc<dyn Hittable + Send + Sync>) -> Self {
+        Transform::new(object, Mat4::identity())
+    }
+
+    /// Apply another transform after this one.
+    pub fn then(self, matrix: Mat4) -> Self {
+        Transform::new(self.object, matrix * self.matrix)
+    }
+
+    pub fn translate(self, offset: Vec3) -> Self {
+        self.then(Mat4::translation(offset))
+    }
+
+    /// Rotate by degrees about an axis through the origin.
+    pub fn rotate(self, axis: Vec3, degrees: f64) -> Self {
+        self.then(Mat4::rotation(axis, degrees))
+    }
+
+    /// Scale by a separate factor along each axis, about the origin.
+    pub fn scale(self, factors: Vec3) -> Self {
+        self.then(Mat4::scaling(factors))
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord> {
+        // Affine transforms keep points along the ray at the same t.
+        let object_ray = Ray::new(
+            self.inverse.transform_point(&r.origin()),
+            self.inverse.transform_vector(&r.direction()),
+            r.time(),
+        );
+        let mut rec = self.object.hit(&object_ray, t_min, t_max, rng)?;
+
+        // Normals are transformed by the inverse transpose to stay
+        // perpendicular to the surface. This keeps them on the same side of
+        // the ray too, so front_face doesn't change.
+        rec.p = self.matrix.transform_point(&rec.p);
+        rec.normal = Vec3::unit_vector(self.inverse.transpose().transform_vector(&rec.normal));
+        Some(rec)
+    }
+
+    fn aabb(&self) -> Option<Aabb> {
+        let bbox = self.object.aabb()?;
+        let (min, max) = (bbox.min(), bbox.max());
+
+        let mut new_min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut new_max = -new_min;
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { min.x() } else { max.x() },
+                if i & 2 == 0 { min.y() } else { max.y() },
+                if i & 4 == 0 { min.z() } else { max.z() },
+            );
+            let p = self.matrix.transform_point(&corner);
+            for axis in 0..3 {
+                new_min[axis] = f64::min(new_min[axis], p[axis]);
+                new_max[axis] = f64::max(new_max[axis], p[axis]);
+            }
+        }
+
+        Some(Aabb::new(new_min, new_max))
+    }
+
+    /// The object's density for the matching direction in object space,
+    /// divided by how much the transform stretches solid angles in that
+    /// direction.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let object_direction = Vec3::unit_vector(self.inverse.transform_vector(direction));
+        let pdf = self
+            .object
+            .pdf_value(&self.inverse.transform_point(origin), &object_direction);
+        let stretch = self.matrix.transform_vector(&object_direction).length();
+        pdf * stretch.powi(3) / self.matrix.determinant().abs()
+    }
+
+    fn random_point(&self, origin: &Point3, rng: &mut Rng) -> Option<Point3> {
+        let point = self
+            .object
+            .random_point(&self.inverse.transform_point(origin), rng)?;
+        Some(self.matrix.transform_point(&point))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hit::Hittable;
+    use crate::material::Lambertian;
+    use crate::random::Rng;
+    use crate::ray::Ray;
+    use crate::sphere::Sphere;
+    use crate::transform::Transform;
+    use crate::utility;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    fn ellipsoid() -> Transform {
+        let sphere = Sphere::new_arc(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        );
+        Transform::identity(sphere)
+            .scale(Vec3::new(2.0, 1.0, 1.0))
+            .rotate(Vec3::new(0.0, 0.0, 1.0), 90.0)
+            .translate(Vec3::new(0.0, 5.0, 0.0))
+    }
+
+    #[test]
+    fn hits_in_world_space() {
+        let mut rng = Rng::new(0);
+        let object = ellipsoid();
+
+        // The ellipsoid is stretched along the y axis, from y = 3 to 7.
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let rec = object.hit(&r, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-9);
+        assert!(rec.front_face);
+
+        let side = Ray::new(Point3::new(-5.0, 5.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let rec = object.hit(&side, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert!((rec.p - Point3::new(-1.0, 5.0, 0.0)).length() < 1e-9);
+
+        let bbox = object.aabb().unwrap();
+        assert!((bbox.min() - Point3::new(-1.0, 3.0, -1.0)).length() < 1e-9);
+        assert!((bbox.max() - Point3::new(1.0, 7.0, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let mut rng = Rng::new(1);
+        let object = ellipsoid();
+        let origin = Point3::new(3.0, 1.0, 0.5);
+
+        // Integrate over all directions by sampling them uniformly.
+        let count = 200_000;
+        let sum: f64 = (0..count)
+            .map(|_| object.pdf_value(&origin, &Vec3::random_unit_vector(&mut rng)))
+            .sum();
+        let integral = 4.0 * utility::PI * sum / count as f64;
+        assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+
+        // Sampled points are on the surface.
+        for _ in 0..100 {
+            let p = object.random_point(&origin, &mut rng).unwrap();
+            let r = Ray::new(origin, p - origin, 0.0);
+            let rec = object.hit(&r, 0.001, f64::INFINITY, &mut rng).unwrap();
+            assert!(rec.t <= 1.0 + 1e-6);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::hit::{HitRecord, Hittable, HittableList};
+use crate::material::Material;
+use crate::random::Rng;
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Padding added to triangle bounding boxes, so that axis aligned triangles
+/// don't have a box with zero thickness.
+const AABB_PADDING: f64 = 0.0001;
+
+/// Vertex data shared by all the triangles of a mesh. If normals or texture
+/// coordinates are present, there is one per position.
+#[derive(Debug, Default, Clone)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<(f64, f64)>>,
+}
+
+/// A single triangle, referencing its vertices in a shared vertex buffer.
+pub struct Triangle {
+    mesh: Arc<MeshData>,
+    indices: [usize; 3],
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Triangle {
+    /// Create a standalone triangle from three points.
+    pub fn new(
+        p0: Point3,
+        p1: Point3,
+        p2: Point3,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        let mesh = MeshData {
+            positions: vec![p0, p1, p2],
+            normals: None,
+            uvs: None,
+        };
+
+        Triangle {
+            mesh: Arc::new(mesh),
+            indices: [0, 1, 2],
+            material,
+        }
+    }
+
+    pub fn new_arc(
+        p0: Point3,
+        p1: Point3,
+        p2: Point3,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Arc<Self> {
+        Arc::new(Triangle::new(p0, p1, p2, material))
+    }
+
+    fn vertex(&self, i: usize) -> Point3 {
+        self.mesh.positions[self.indices[i]]
+    }
+
+    /// Möller–Trumbore ray triangle intersection. The u, v coordinates of the
+    /// hit record are the interpolated texture coordinates if the mesh has
+    /// them, otherwise the barycentric coordinates of the hit.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let p0 = self.vertex(0);
+        let edge1 = self.vertex(1) - p0;
+        let edge2 = self.vertex(2) - p0;
+
+        let pvec = Vec3::cross(&r.direction(), &edge2);
+        let det = Vec3::dot(&edge1, &pvec);
+        // The ray is parallel to the triangle.
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin() - p0;
+        let u = Vec3::dot(&tvec, &pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let qvec = Vec3::cross(&tvec, &edge1);
+        let v = Vec3::dot(&r.direction(), &qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = Vec3::dot(&edge2, &qvec) * inv_det;
+        if t >= t_max || t <= t_min {
+            return None;
+        }
+
+        let geometric_normal = Vec3::unit_vector(Vec3::cross(&edge1, &edge2));
+        let outward_normal = match &self.mesh.normals {
+            Some(normals) => {
+                let n = (1.0 - u - v) * normals[self.indices[0]]
+                    + u * normals[self.indices[1]]
+                    + v * normals[self.indices[2]];
+                let n = Vec3::unit_vector(n);
+                // Keep the shading normal on the same side as the surface.
+                if Vec3::dot(&n, &geometric_normal) < 0.0 {
+                    -n
+                } else {
+                    n
+                }
+            }
+            None => geometric_normal,
+        };
+
+        let (tex_u, tex_v) = match &self.mesh.uvs {
+            Some(uvs) => {
+                let uv0 = uvs[self.indices[0]];
+                let uv1 = uvs[self.indices[1]];
+                let uv2 = uvs[self.indices[2]];
+                (
+                    (1.0 - u - v) * uv0.0 + u * uv1.0 + v * uv2.0,
+                    (1.0 - u - v) * uv0.1 + u * uv1.1 + v * uv2.1,
+                )
+            }
+            None => (u, v),
+        };
+
+        Some(HitRecord::new(
+            r.at(t),
+            r,
+            outward_normal,
+            t,
+            tex_u,
+            tex_v,
+            self.material.clone(),
+        ))
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut Rng) -> Option<HitRecord> {
+        self.intersect(r, t_min, t_max)
+    }
+
+    fn aabb(&self) -> Option<Aabb> {
+        let mut min = self.vertex(0);
+        let mut max = self.vertex(0);
+        for i in 1..3 {
+            let p = self.vertex(i);
+            for axis in 0..3 {
+                min[axis] = f64::min(min[axis], p[axis]);
+                max[axis] = f64::max(max[axis], p[axis]);
+            }
+        }
+
+        let padding = Vec3::new(AABB_PADDING, AABB_PADDING, AABB_PADDING);
+        Some(Aabb::new(min - padding, max + padding))
+    }
+
+    /// Points are chosen uniformly over the area of the triangle.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let rec = match self.intersect(&Ray::new(*origin, *direction, 0.0), 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+
+        let cross = Vec3::cross(
+            &(self.vertex(1) - self.vertex(0)),
+            &(self.vertex(2) - self.vertex(0)),
+        );
+        let area = 0.5 * cross.length();
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = Vec3::dot(direction, &cross).abs() / (direction.length() * cross.length());
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random_point(&self, _origin: &Point3, rng: &mut Rng) -> Option<Point3> {
+        let s = rng.random_f64().sqrt();
+        let r = rng.random_f64();
+        Some((1.0 - s) * self.vertex(0) + s * (1.0 - r) * self.vertex(1) + s * r * self.vertex(2))
+    }
+}
+
+/// A collection of triangles sharing a single vertex buffer and material,
+/// accelerated by its own BVH.
+pub struct TriangleMesh {
+    bvh: BvhNode,
+    /// The triangles, kept for sampling points on the mesh.
+    triangles: HittableList,
+}
+
+impl TriangleMesh {
+    /// Create a mesh from vertex data and a list of triangles, each given by the
+    /// indices of its three vertices.
+    pub fn new(
+        mesh: MeshData,
+        triangles: &[[usize; 3]],
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        if let Some(normals) = &mesh.normals {
+            assert_eq!(normals.len(), mesh.positions.len());
+        }
+        if let Some(uvs) = &mesh.uvs {
+            assert_eq!(uvs.len(), mesh.positions.len());
+        }
+
+        let mesh = Arc::new(mesh);
+        let mut list = HittableList::default();
+        for indices in triangles {
+            assert!(indices.iter().all(|&i| i < mesh.positions.len()));
+            list.add(Arc::new(Triangle {
+                mesh: mesh.clone(),
+                indices: *indices,
+                material: material.clone(),
+            }));
+        }
+
+        TriangleMesh {
+            bvh: BvhNode::new(list.clone()),
+            triangles: list,
+        }
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max, rng)
+    }
+
+    fn aabb(&self) -> Option<Aabb> {
+        self.bvh.aabb()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.triangles.pdf_value(origin, direction)
+    }
+
+    fn random_point(&self, origin: &Point3, rng: &mut Rng) -> Option<Point3> {
+        self.triangles.random_point(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hit::Hittable;
+    use crate::material::Lambertian;
+    use crate::random::Rng;
+    use crate::ray::Ray;
+    use crate::triangle::{MeshData, Triangle, TriangleMesh};
+    use crate::vec3::{Color, Point3, Vec3};
+
+    #[test]
+    fn triangle_hit_barycentric() {
+        let mut rng = Rng::new(0);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let triangle = Triangle::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            material,
+        );
+
+        let r = Ray::new(Point3::new(0.25, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = triangle.hit(&r, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert_eq!(rec.t, 1.0);
+        assert_eq!((rec.u, rec.v), (0.25, 0.5));
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+
+        let miss = Ray::new(Point3::new(0.75, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(triangle
+            .hit(&miss, 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn mesh_smooth_normals() {
+        let mut rng = Rng::new(0);
+        let material = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let tilted = Vec3::unit_vector(Vec3::new(1.0, 1.0, 0.0));
+        let mesh = MeshData {
+            positions: vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 0.0, -1.0),
+                Point3::new(1.0, 0.0, -1.0),
+            ],
+            normals: Some(vec![up, tilted, up, tilted]),
+            uvs: None,
+        };
+        let mesh = TriangleMesh::new(mesh, &[[0, 1, 2], [1, 3, 2]], material);
+
+        let r = Ray::new(Point3::new(1.0, 1.0, -0.5), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let rec = mesh.hit(&r, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert!((rec.normal - tilted).length() < 1e-9);
+    }
+}
@@ -1,4 +1,5 @@
 /// pi in the book is defined different than the std f64 one.
+#[allow(clippy::approx_constant, clippy::excessive_precision)]
 pub const PI: f64 = 3.1415926535897932385;
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
@@ -18,11 +19,30 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     x
 }
 
-pub fn random_f64() -> f64 {
-    rand::random::<f64>()
+/// Encode a linear value with the sRGB transfer function.
+pub fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
 }
 
-// Random value for a range [min, max)
-pub fn random_f64_range(min: f64, max: f64) -> f64 {
-    min + (max - min) * rand::random::<f64>()
+/// Decode an sRGB encoded value to linear, the inverse of `linear_to_srgb`.
+pub fn srgb_to_linear(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The power heuristic (with an exponent of 2) for weighting a sample taken
+/// with pdf `f` against another strategy with pdf `g` in multiple importance
+/// sampling.
+pub fn power_heuristic(f: f64, g: f64) -> f64 {
+    if f <= 0.0 {
+        return 0.0;
+    }
+    (f * f) / (f * f + g * g)
 }
@@ -1,5 +1,5 @@
+use crate::random::Rng;
 use crate::utility;
-use crate::utility::{random_f64, random_f64_range};
 use std::fmt;
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub};
 
@@ -51,23 +51,23 @@ impl Vec3 {
     }
 
     /// Get a random unit vector.
-    pub fn random() -> Self {
-        Vec3(random_f64(), random_f64(), random_f64())
+    pub fn random(rng: &mut Rng) -> Self {
+        Vec3(rng.random_f64(), rng.random_f64(), rng.random_f64())
     }
 
     /// Get a random vector with a given min/max range.
-    pub fn random_range(min: f64, max: f64) -> Self {
+    pub fn random_range(rng: &mut Rng, min: f64, max: f64) -> Self {
         Vec3(
-            random_f64_range(min, max),
-            random_f64_range(min, max),
-            random_f64_range(min, max),
+            rng.random_f64_range(min, max),
+            rng.random_f64_range(min, max),
+            rng.random_f64_range(min, max),
         )
     }
 
     /// Get a random vector within a unit sphere.
-    pub fn random_in_unit_sphere() -> Self {
+    pub fn random_in_unit_sphere(rng: &mut Rng) -> Self {
         loop {
-            let p = Vec3::random_range(-1.0, 1.0);
+            let p = Vec3::random_range(rng, -1.0, 1.0);
             if p.length_squared() >= 1.0 {
                 continue;
             }
@@ -76,16 +76,16 @@ impl Vec3 {
     }
 
     /// Get a Lambertian distrubuted unit vector, see Section 8.5.
-    pub fn random_unit_vector() -> Self {
-        let a = random_f64_range(0.0, 2.0 * utility::PI);
-        let z = random_f64_range(-1.0, 1.0);
+    pub fn random_unit_vector(rng: &mut Rng) -> Self {
+        let a = rng.random_f64_range(0.0, 2.0 * utility::PI);
+        let z = rng.random_f64_range(-1.0, 1.0);
         let r = f64::sqrt(1.0 - z * z);
         Vec3(r * a.cos(), r * a.sin(), z)
     }
 
     /// Get an alternative diffuse vector, see Section 8.6.
-    pub fn random_in_hemisphere(normal: &Vec3) -> Self {
-        let in_unit_sphere = Vec3::random_in_unit_sphere();
+    pub fn random_in_hemisphere(rng: &mut Rng, normal: &Vec3) -> Self {
+        let in_unit_sphere = Vec3::random_in_unit_sphere(rng);
         if Vec3::dot(&in_unit_sphere, normal) > 0.0
         // In the same hemisphere as the normal
         {
@@ -106,11 +106,11 @@ impl Vec3 {
         r_out_parallel + r_out_perp
     }
 
-    pub fn random_in_unit_disk() -> Self {
+    pub fn random_in_unit_disk(rng: &mut Rng) -> Self {
         loop {
             let p = Vec3(
-                random_f64_range(-1.0, 1.0),
-                random_f64_range(-1.0, 1.0),
+                rng.random_f64_range(-1.0, 1.0),
+                rng.random_f64_range(-1.0, 1.0),
                 0.0,
             );
             if p.length_squared() >= 1.0 {
@@ -280,35 +280,28 @@ impl Color {
         Color(vec)
     }
 
-    pub fn random() -> Self {
-        Color(Vec3(random_f64(), random_f64(), random_f64()))
+    pub fn random(rng: &mut Rng) -> Self {
+        Color(Vec3::random(rng))
     }
 
-    pub fn get_color_string(&self, samples_per_pixel: i32) -> String {
-        let mut r: f64 = self.x();
-        let mut g: f64 = self.y();
-        let mut b: f64 = self.z();
-
-        // Divide the color total by the number of samples and gamma-correct for gamma=2.0.
-        let scale = 1.0 / samples_per_pixel as f64;
-        r = f64::sqrt(scale * r);
-        g = f64::sqrt(scale * g);
-        b = f64::sqrt(scale * b);
+    /// The relative luminance of a linear color.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x() + 0.7152 * self.y() + 0.0722 * self.z()
+    }
 
-        // Write the translated [0,255] value of each color component.
-        format!(
-            "{} {} {}\n",
-            (256.0 * utility::clamp(r, 0.0, 0.999)) as i32,
-            (256.0 * utility::clamp(g, 0.0, 0.999)) as i32,
-            (256.0 * utility::clamp(b, 0.0, 0.999)) as i32,
-        )
+    /// Convert a linear color to sRGB encoded 8-bit rgb values, clamping each
+    /// component to [0, 1).
+    pub fn to_rgb8(self) -> [u8; 3] {
+        let convert =
+            |x: f64| (256.0 * utility::clamp(utility::linear_to_srgb(x), 0.0, 0.999)) as u8;
+        [convert(self.x()), convert(self.y()), convert(self.z())]
     }
 }
 
 // TODO - more tests
 #[cfg(test)]
 mod tests {
-    use crate::vec3::Vec3;
+    use crate::vec3::{Color, Vec3};
 
     #[test]
     fn vec3_binary_ops() {
@@ -317,4 +310,11 @@ mod tests {
             Vec3(2.0, 2.0, 1.5)
         );
     }
+
+    #[test]
+    fn rgb8_is_srgb_encoded() {
+        // Linear mid-grey is a little under half way in sRGB.
+        assert_eq!(Color::new(0.18, 0.0, 1.0).to_rgb8(), [118, 0, 255]);
+        assert_eq!(Color::new(0.214, 0.002, 2.0).to_rgb8(), [127, 6, 255]);
+    }
 }